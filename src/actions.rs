@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+use std::path::{Component, Path};
+use thiserror::Error;
+
+use crate::Task;
+
+/// `deploy get` 运行的动作
+pub const DEFAULT_ACTION: &str = "install";
+
+/// 没有声明 install 动作时使用的脚本
+pub const DEFAULT_SCRIPT: &str = "run.sh";
+
+/// 检查本机是否已经应用过任务的动作，退出码为 0 表示已应用
+pub const CHECK_ACTION: &str = "check";
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ActionError {
+    #[error("invalid action name `{0}`, use lowercase letters, digits, `-` and `_`")]
+    InvalidName(String),
+    #[error("script `{1}` of action `{0}` must be a relative path inside the task")]
+    InvalidScript(String, String),
+    #[error("task has no action `{0}`, available: {1}")]
+    Unknown(String, String),
+}
+
+impl Task {
+    /// 所有可用的动作及其脚本，没有声明 install 时默认运行 run.sh
+    pub fn actions(&self) -> BTreeMap<String, String> {
+        let mut actions = self.actions.clone();
+        actions
+            .entry(DEFAULT_ACTION.to_string())
+            .or_insert_with(|| DEFAULT_SCRIPT.to_string());
+        actions
+    }
+
+    /// 声明的检查脚本
+    pub fn check_script(&self) -> Option<&str> {
+        self.actions.get(CHECK_ACTION).map(|s| s.as_str())
+    }
+
+    /// 查找动作对应的脚本
+    pub fn action_script(&self, action: &str) -> Result<String, ActionError> {
+        let actions = self.actions();
+        actions.get(action).cloned().ok_or_else(|| {
+            ActionError::Unknown(
+                action.to_string(),
+                actions.keys().cloned().collect::<Vec<_>>().join(", "),
+            )
+        })
+    }
+}
+
+/// 检查动作名和脚本路径，脚本必须是任务目录里的相对路径
+pub fn check_actions(actions: &BTreeMap<String, String>) -> Result<(), ActionError> {
+    for (name, script) in actions {
+        let valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+        if !valid_name {
+            return Err(ActionError::InvalidName(name.clone()));
+        }
+        let valid_script = !script.is_empty()
+            && Path::new(script)
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)));
+        if !valid_script {
+            return Err(ActionError::InvalidScript(name.clone(), script.clone()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_actions() {
+        let mut task: Task = toml::from_str(
+            "uuid = \"u\"\nname = \"app\"\ndescription = \"\"\n[actions]\nuninstall = \"scripts/uninstall.sh\"\n",
+        )
+        .unwrap();
+        assert_eq!(task.action_script("install"), Ok("run.sh".to_string()));
+        assert_eq!(task.action_script("uninstall"), Ok("scripts/uninstall.sh".to_string()));
+        assert!(matches!(task.action_script("upgrade"), Err(ActionError::Unknown(..))));
+        assert_eq!(task.check_script(), None);
+        assert_eq!(check_actions(&task.actions), Ok(()));
+        task.actions.insert("verify".to_string(), "../../bin/sh".to_string());
+        assert!(matches!(check_actions(&task.actions), Err(ActionError::InvalidScript(..))));
+        task.actions.clear();
+        task.actions.insert("Verify!".to_string(), "verify.sh".to_string());
+        assert!(matches!(check_actions(&task.actions), Err(ActionError::InvalidName(..))));
+    }
+}
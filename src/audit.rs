@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+
+/// 会记录审计日志的操作类型，服务端的路由和客户端的 `--action` 都以它为准
+pub const AUDIT_ACTIONS: &[&str] = &[
+    "upload",
+    "delete",
+    "update",
+    "import",
+    "gc",
+    "rollback",
+    "secret-set",
+    "secret-rm",
+    "profile-set",
+    "profile-rm",
+];
+
+/// 一条修改操作的审计记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Unix 时间戳（秒）
+    pub timestamp: u64,
+    /// 调用方的令牌名或证书 CN
+    pub actor: String,
+    pub source_ip: String,
+    /// 操作类型，取值见 [`AUDIT_ACTIONS`]
+    pub action: String,
+    /// 涉及的任务，重建索引时为空
+    pub task: Option<String>,
+    /// 操作前后任务内容的哈希，任务不存在时为空
+    pub previous_hash: Option<String>,
+    pub new_hash: Option<String>,
+    /// 响应的状态码
+    pub status: u16,
+    pub success: bool,
+}
+
+/// 查询审计记录的过滤条件，对应 `GET /audit` 的查询参数
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    /// 任务名或完整的任务标识
+    pub task: Option<String>,
+    pub action: Option<String>,
+    /// 只返回该时间戳及之后的记录
+    pub since: Option<u64>,
+    /// 只返回该时间戳之前的记录
+    pub until: Option<u64>,
+    /// 最多返回的条数，按时间倒序
+    pub limit: Option<u64>,
+}
+
+/// 1970-01-01 之后的天数换算为公历的（年，月，日），见 Howard Hinnant 的 civil_from_days
+pub(crate) fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+/// civil_from_days 的逆运算
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// 把 Unix 时间戳格式化为 `YYYY-MM-DD HH:MM:SS` 的 UTC 时间
+pub fn format_timestamp(timestamp: u64) -> String {
+    let secs = timestamp % 86400;
+    let (year, month, day) = civil_from_days((timestamp / 86400) as i64);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(951782400), "2000-02-29 00:00:00");
+        assert_eq!(format_timestamp(1745000000), "2025-04-18 18:13:20");
+    }
+}
@@ -0,0 +1,153 @@
+use argon2::password_hash::{PasswordHasher, SaltString, rand_core::OsRng};
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use deploycli::{SignedHeader, unix_now};
+use log::{debug, error};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+use crate::config::{CFG, Token};
+
+/// 令牌的权限范围，admin 拥有全部权限
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Read,
+    Upload,
+    Delete,
+    Admin,
+}
+
+/// 通过认证的调用方，由 auth_middleware 注入到 Depot 中
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+}
+
+impl Identity {
+    /// 判断是否拥有某个权限
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.iter().any(|s| *s == scope || *s == Scope::Admin)
+    }
+}
+
+/// 已使用过的 nonce 及其时间戳，用于拒绝重放的签名请求
+static NONCES: LazyLock<Mutex<HashMap<String, u64>>> = LazyLock::new(Default::default);
+
+impl From<&Token> for Identity {
+    fn from(token: &Token) -> Self {
+        Identity {
+            name: token.name.clone(),
+            scopes: token.scopes.clone(),
+        }
+    }
+}
+
+/// 解析 `<token名>:<密钥>` 格式的 Authorization 头并校验对应令牌
+pub fn authenticate(auth_header: &str) -> Option<Identity> {
+    let (name, secret) = auth_header.split_once(':')?;
+    let token = CFG.server.tokens.iter().find(|t| t.name == name)?;
+    if token.require_signature {
+        return None;
+    }
+    if !verify_password(secret, token.hash.as_deref()?) {
+        return None;
+    }
+    Some(token.into())
+}
+
+/// 通过客户端证书认证：证书 CN 对应同名的令牌
+pub fn authenticate_cert(subject: &str) -> Option<Identity> {
+    let token = CFG.server.tokens.iter().find(|t| t.name == subject)?;
+    Some(token.into())
+}
+
+/// 只看认证头能做的检查：令牌存在、配置了签名密钥且时间戳未过期。
+/// 通过后才值得读取请求体去校验签名
+pub fn signing_token(header: &SignedHeader) -> Option<&'static Token> {
+    let token = CFG.server.tokens.iter().find(|t| t.name == header.token)?;
+    token.signing_key.as_ref()?;
+    if unix_now().abs_diff(header.timestamp) > CFG.server.signature_max_age {
+        debug!("Stale signature from {}", header.token);
+        return None;
+    }
+    Some(token)
+}
+
+/// 校验签名请求：签名正确、时间戳未过期且 nonce 未被使用过
+pub fn authenticate_signed(
+    header: &SignedHeader,
+    method: &str,
+    path: &str,
+    body: &[u8],
+) -> Option<Identity> {
+    let token = signing_token(header)?;
+    let key = token.signing_key.as_deref()?;
+    let now = unix_now();
+    let max_age = CFG.server.signature_max_age;
+    if !header.verify(key, method, path, body) {
+        return None;
+    }
+    let mut nonces = NONCES.lock().unwrap_or_else(|e| e.into_inner());
+    nonces.retain(|_, ts| now.abs_diff(*ts) <= max_age);
+    let nonce_key = format!("{}:{}", header.token, header.nonce);
+    if nonces.contains_key(&nonce_key) {
+        debug!("Replayed nonce from {}", header.token);
+        return None;
+    }
+    nonces.insert(nonce_key, header.timestamp);
+    Some(token.into())
+}
+
+/// 生成带随机盐的 argon2id 密码哈希，结果为 PHC 字符串，可直接写入 config.toml
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("Failed to hash password: {}", e))?;
+    Ok(hash.to_string())
+}
+
+/// 校验密码是否与配置中的哈希匹配，比较过程是常数时间的
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    let parsed = match PasswordHash::new(hash) {
+        Ok(h) => h,
+        Err(e) => {
+            error!("Invalid password hash in config: {}", e);
+            return false;
+        }
+    };
+    Argon2::default()
+        .verify_password(password.as_bytes(), &parsed)
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_and_verify() {
+        let hash = hash_password("password").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("password", &hash));
+        assert!(!verify_password("wrong", &hash));
+        assert!(!verify_password("password", "not a hash"));
+    }
+
+    #[test]
+    fn test_scopes() {
+        let reader = Identity {
+            name: "host".to_string(),
+            scopes: vec![Scope::Read],
+        };
+        assert!(reader.allows(Scope::Read));
+        assert!(!reader.allows(Scope::Upload));
+        let admin = Identity {
+            name: "admin".to_string(),
+            scopes: vec![Scope::Admin],
+        };
+        assert!(admin.allows(Scope::Delete));
+    }
+}
@@ -1,21 +1,59 @@
 use anyhow::anyhow;
+use clap::builder::PossibleValuesParser;
 use clap::{Parser, Subcommand};
 use colored::Colorize;
-use deploycli::{run_script, Task};
-use deploycli::{create_zip, unpack_zip};
-use reqwest::blocking::{Client, multipart};
+use deploycli::{client_tls_config, run_check, run_script, SignedHeader, Task, TaskId};
+use deploycli::{author_public_key, generate_author_key, sign_package, verify_package};
+use deploycli::{UnpackLimits, apply_modes, create_zip, unpack_zip_with_limits};
+use deploycli::{GcReport, PackageEntry, ignored_entries, package_entries};
+use deploycli::{AUDIT_ACTIONS, AuditFilter, AuditRecord, format_timestamp, unix_now};
+use deploycli::{Profile, SecretInfo, TaskVersion, is_valid_secret_name};
+use deploycli::{Param, ParamError, ParamKind, ParamValue, load_manifest, resolve_params};
+use deploycli::{CHECK_ACTION, DEFAULT_ACTION, HostFacts, TaskQuery, content_hash, dependency_order, find_task};
+use reqwest::blocking::{Client, RequestBuilder, Response, multipart};
+use reqwest::StatusCode;
+use reqwest::header::{AUTHORIZATION, HeaderValue, RETRY_AFTER};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::HashMap;
 use std::path::Path;
 use std::process;
 use std::fs;
+use std::io::Write;
 
 const CONFIG_PATH: &str = "/etc/deploycli/config.toml";
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Config {
     server: String,
+    /// `<令牌名>:<密钥>`
     password: String,
+    /// 配置后对请求做 HMAC 签名，不再发送明文密钥
+    signing: Option<Signing>,
+    /// PEM 格式的 CA 证书包路径，用于信任自签名的服务端证书
+    ca_cert: Option<String>,
+    /// 服务端证书的 SHA-256 指纹，设置后只接受该证书
+    fingerprint: Option<String>,
+    /// 双向 TLS 使用的客户端证书（PEM）路径
+    client_cert: Option<String>,
+    /// 客户端证书对应的私钥（PEM）路径
+    client_key: Option<String>,
+    /// 作者私钥文件路径，上传任务时用它签名，由 `deploy keygen` 生成
+    author_key: Option<String>,
+    /// 信任的作者公钥，名字 = 公钥，只运行由这些密钥签名的任务
+    #[serde(default)]
+    trusted_keys: HashMap<String, String>,
+    /// 解压任务包时的资源限制
+    #[serde(default)]
+    unpack: UnpackLimits,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Signing {
+    /// 服务端配置中的令牌名
+    token: String,
+    /// 与服务端 `signing_key` 相同的共享密钥
+    key: String,
 }
 
 /// CLI client for task management
@@ -38,27 +76,207 @@ enum Commands {
         /// Name of the new task
         name: String,
     },
-    /// Get tasks or a specific task by index
+    /// List tasks or run a specific task
     Get {
-        /// Index of the task to get
-        index: Option<usize>,
+        /// Name, uuid or uuid prefix of the task to run
+        task: Option<String>,
+        /// Run an older version instead of the latest
+        #[arg(long, requires = "task")]
+        version: Option<u32>,
+        /// Set a task parameter, can be repeated
+        #[arg(long = "set", value_name = "KEY=VALUE", requires = "task")]
+        set: Vec<String>,
+        /// TOML file with parameter values
+        #[arg(long, value_name = "FILE", requires = "task")]
+        values: Option<String>,
+        /// Run only this task, without its dependencies
+        #[arg(long, requires = "task")]
+        no_deps: bool,
+        /// Run the task even if this host doesn't match its platform
+        #[arg(long, requires = "task")]
+        force: bool,
+        /// Only list tasks with this tag
+        #[arg(long, conflicts_with = "task")]
+        tag: Option<String>,
+    },
+    /// Run a lifecycle action of a task, such as uninstall, upgrade or verify
+    Run {
+        /// Name, uuid or uuid prefix of the task
+        task: String,
+        /// Name of the action declared in the task's `[actions]`
+        action: String,
+        /// Set a task parameter, can be repeated
+        #[arg(long = "set", value_name = "KEY=VALUE")]
+        set: Vec<String>,
+        /// TOML file with parameter values
+        #[arg(long, value_name = "FILE")]
+        values: Option<String>,
+        /// Run the action even if this host doesn't match the task's platform
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check whether tasks are already applied on this host
+    Check {
+        /// Name, uuid or uuid prefix of the task to check
+        #[arg(required_unless_present = "all")]
+        task: Option<String>,
+        /// Check every task
+        #[arg(long, conflicts_with = "task")]
+        all: bool,
+        /// Run the check scripts without showing them and asking first
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// Search tasks by name, description, tag or category
+    Search {
+        /// Text to match in the name or description
+        text: Option<String>,
+        /// Only show tasks with this tag
+        #[arg(long)]
+        tag: Option<String>,
+        /// Only show tasks in this category
+        #[arg(long)]
+        category: Option<String>,
+        /// Sort by `name` or `category`, prefix with `-` for descending
+        #[arg(long, allow_hyphen_values = true)]
+        sort: Option<String>,
+        /// Maximum number of tasks to show
+        #[arg(long)]
+        limit: Option<usize>,
+        /// Number of tasks to skip
+        #[arg(long)]
+        offset: Option<usize>,
     },
     /// Upload a task
     Post {
         /// Path to the task file to upload
         path: String,
+        /// List the files that would be uploaded and their total size without uploading
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Validate a task directory without uploading it
+    Lint {
+        /// Path to the task directory
+        path: String,
     },
     /// Delete a task
     Delete {
-        /// Index of the task to delete
-        index: usize,
+        /// Name, uuid or uuid prefix of the task to delete
+        task: String,
     },
     /// Update remote database index
     Update,
+    /// Import the task directories placed in the server's ./tasks as new versions
+    Import,
+    /// Reclaim server storage no longer used by any task version
+    Gc,
     /// CLean local cache
     Clean {
-        /// Index of the task to clean
-        index: Option<usize>,
+        /// Name, uuid or uuid prefix of the task to clean
+        task: Option<String>,
+    },
+    /// Generate an author key for signing task packages
+    Keygen {
+        /// Path to write the private key to
+        path: String,
+    },
+    /// List the versions of a task
+    Versions {
+        /// Name, uuid or uuid prefix of the task
+        task: String,
+    },
+    /// Make an older version of a task the latest again
+    Rollback {
+        /// Name, uuid or uuid prefix of the task
+        task: String,
+        /// Version to roll back to
+        version: u32,
+    },
+    /// Manage secrets passed to a task's run.sh as environment variables
+    Secret {
+        #[command(subcommand)]
+        command: SecretCommands,
+    },
+    /// Manage profiles, ordered lists of tasks run together
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommands,
+    },
+    /// Show who changed tasks on the server
+    Audit {
+        /// Only show operations by this token
+        #[arg(long)]
+        actor: Option<String>,
+        /// Only show operations on this task (name or `<name>-<uuid>`)
+        #[arg(long)]
+        task: Option<String>,
+        /// Only show this action
+        #[arg(long, value_parser = PossibleValuesParser::new(AUDIT_ACTIONS))]
+        action: Option<String>,
+        /// Only show operations in the last N hours
+        #[arg(long)]
+        hours: Option<u64>,
+        /// Maximum number of records to show
+        #[arg(long, default_value_t = 50)]
+        limit: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum ProfileCommands {
+    /// List the profiles on the server
+    List,
+    /// Show the tasks and parameters of a profile
+    Show {
+        /// Name of the profile
+        name: String,
+    },
+    /// Create or replace a profile from a TOML file
+    Set {
+        /// Path to the profile file
+        path: String,
+    },
+    /// Remove a profile
+    Rm {
+        /// Name of the profile
+        name: String,
+    },
+    /// Run every task of a profile in order and print a summary
+    Apply {
+        /// Name of the profile
+        name: String,
+        /// Run tasks even if this host doesn't match their platform
+        #[arg(long)]
+        force: bool,
+        /// Continue with the remaining tasks after a failure
+        #[arg(long)]
+        keep_going: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SecretCommands {
+    /// Set a secret, the value is read from stdin if omitted
+    Set {
+        /// Name, uuid or uuid prefix of the task
+        task: String,
+        /// Name of the environment variable
+        name: String,
+        /// Value of the secret
+        value: Option<String>,
+    },
+    /// List the names of a task's secrets
+    List {
+        /// Name, uuid or uuid prefix of the task
+        task: String,
+    },
+    /// Remove a secret
+    Rm {
+        /// Name, uuid or uuid prefix of the task
+        task: String,
+        /// Name of the environment variable
+        name: String,
     },
 }
 
@@ -81,7 +299,13 @@ fn main() {
     };
 
     // 创建 HTTP 客户端
-    let client = Client::new();
+    let client = match build_client(&config) {
+        Ok(client) => client,
+        Err(err) => {
+            eprintln!("Error creating HTTP client: {}", err);
+            process::exit(1);
+        }
+    };
 
     // 解析命令并执行
     match cli.command {
@@ -91,28 +315,92 @@ fn main() {
                 process::exit(1);
             }
         }
-        Commands::Get { index } => {
-            if index.is_none() {
-                if let Err(e) = list_tasks(&client, &config) {
+        Commands::Get {
+            task,
+            version,
+            set,
+            values,
+            no_deps,
+            force,
+            tag,
+        } => {
+            let Some(task) = task else {
+                let query = TaskQuery {
+                    tag,
+                    ..Default::default()
+                };
+                if let Err(e) = list_tasks(&client, &config, &query) {
                     eprintln!("Error: Failed to list tasks. Caused by: {e}");
                     process::exit(1);
                 }
                 return;
-            }
-            let index = index.unwrap();
-            if let Err(e) = get_task_by_index(&client, &config, index) {
+            };
+            let provided = match provided_params(&set, values.as_deref()) {
+                Ok(provided) => provided,
+                Err(e) => {
+                    eprintln!("Error: Invalid parameters. Caused by: {e}");
+                    process::exit(1);
+                }
+            };
+            let options = GetOptions {
+                version,
+                provided,
+                no_deps,
+                force,
+                action: DEFAULT_ACTION.to_string(),
+            };
+            if let Err(e) = get_task(&client, &config, &task, &options) {
                 eprintln!("Error: Failed to get task. Caused by: {e}");
                 process::exit(1);
             }
         }
-        Commands::Post { path } => {
+        Commands::Run {
+            task,
+            action,
+            set,
+            values,
+            force,
+        } => {
+            let provided = match provided_params(&set, values.as_deref()) {
+                Ok(provided) => provided,
+                Err(e) => {
+                    eprintln!("Error: Invalid parameters. Caused by: {e}");
+                    process::exit(1);
+                }
+            };
+            // 只有 install 会先运行依赖
+            let options = GetOptions {
+                version: None,
+                provided,
+                no_deps: action != DEFAULT_ACTION,
+                force,
+                action,
+            };
+            if let Err(e) = get_task(&client, &config, &task, &options) {
+                eprintln!("Error: Failed to run task. Caused by: {e}");
+                process::exit(1);
+            }
+        }
+        Commands::Post { path, dry_run: true } => {
+            if let Err(e) = preview_upload(&path) {
+                eprintln!("Error: {e}");
+                process::exit(1);
+            }
+        }
+        Commands::Post { path, dry_run: false } => {
             if let Err(e) = upload_task(&client, &config, &path) {
                 eprintln!("Error: Failed to upload task. Caused by: {e}");
                 process::exit(1);
             }
         }
-        Commands::Delete { index } => {
-            if let Err(e) = delete_task(&client, &config, index) {
+        Commands::Lint { path } => {
+            if let Err(e) = lint_task(&path) {
+                eprintln!("{e}");
+                process::exit(1);
+            }
+        }
+        Commands::Delete { task } => {
+            if let Err(e) = delete_task(&client, &config, &task) {
                 eprintln!("Error: Failed to delete task. Caused by: {e}");
                 process::exit(1);
             }
@@ -123,24 +411,151 @@ fn main() {
                 process::exit(1);
             }
         }
-        Commands::Clean { index } => {
-            if let Err(e) = clean_cache(&client, &config, index) {
+        Commands::Import => {
+            if let Err(e) = import_tasks(&client, &config) {
+                eprintln!("Error: Failed to import tasks. Caused by: {e}");
+                process::exit(1);
+            }
+        }
+        Commands::Gc => {
+            if let Err(e) = collect_garbage(&client, &config) {
+                eprintln!("Error: Failed to collect garbage. Caused by: {e}");
+                process::exit(1);
+            }
+        }
+        Commands::Clean { task } => {
+            if let Err(e) = clean_cache(&client, &config, task.as_deref()) {
                 eprintln!("Error: Failed to clean cache. Caused by: {e}");
                 process::exit(1);
             }
         }
+        Commands::Keygen { path } => {
+            if let Err(e) = generate_key(&path) {
+                eprintln!("Error: Failed to generate key. Caused by: {e}");
+                process::exit(1);
+            }
+        }
+        Commands::Check { task, all: _, yes } => {
+            if let Err(e) = check_tasks(&client, &config, task.as_deref(), yes) {
+                eprintln!("Error: {e}");
+                process::exit(1);
+            }
+        }
+        Commands::Search {
+            text,
+            tag,
+            category,
+            sort,
+            limit,
+            offset,
+        } => {
+            let query = TaskQuery {
+                tag,
+                category,
+                q: text,
+                sort,
+                limit,
+                offset,
+            };
+            if let Err(e) = list_tasks(&client, &config, &query) {
+                eprintln!("Error: Failed to search tasks. Caused by: {e}");
+                process::exit(1);
+            }
+        }
+        Commands::Versions { task } => {
+            if let Err(e) = list_versions(&client, &config, &task) {
+                eprintln!("Error: Failed to list versions. Caused by: {e}");
+                process::exit(1);
+            }
+        }
+        Commands::Rollback { task, version } => {
+            if let Err(e) = rollback_task(&client, &config, &task, version) {
+                eprintln!("Error: Failed to roll back task. Caused by: {e}");
+                process::exit(1);
+            }
+        }
+        Commands::Secret { command } => {
+            if let Err(e) = manage_secrets(&client, &config, command) {
+                eprintln!("Error: Failed to manage secrets. Caused by: {e}");
+                process::exit(1);
+            }
+        }
+        Commands::Profile { command } => {
+            if let Err(e) = manage_profiles(&client, &config, command) {
+                eprintln!("Error: Failed to manage profiles. Caused by: {e}");
+                process::exit(1);
+            }
+        }
+        Commands::Audit {
+            actor,
+            task,
+            action,
+            hours,
+            limit,
+        } => {
+            let filter = AuditFilter {
+                actor,
+                task,
+                action,
+                since: hours.map(|h| unix_now().saturating_sub(h * 3600)),
+                until: None,
+                limit: Some(limit),
+            };
+            if let Err(e) = list_audit(&client, &config, &filter) {
+                eprintln!("Error: Failed to get audit log. Caused by: {e}");
+                process::exit(1);
+            }
+        }
     }
 }
 
 fn create_new_task(name: &str) -> anyhow::Result<()> {
     // 创建一个新的任务目录，输入config.toml和run.sh脚本
+    let uuid = uuid::Uuid::new_v4();
+    // 任务名只能包含字母、数字、`-` 和 `_`
+    TaskId::parse(name, &uuid.to_string())?;
     fs::create_dir(name)?;
-    let config_content = r#"uuid = "{uuid}"
+    let config_content = r#"# 清单格式版本，`deploy lint` 按此校验
+schema = 1
+uuid = "{uuid}"
 name = "{name}"
 description = "This is an example task"
-"#;
-    let uuid = uuid::Uuid::new_v4();
+# 运行前需要先运行的任务，填任务名或 uuid
+# depends_on = ["base", "docker"]
+# 用于 `deploy search` 的分类和标签
+# category = "web"
+# tags = ["nginx", "proxy"]
 
+# 任务能运行的平台，每项可以写一个值或列表，不写表示不限
+# [platform]
+# os = "linux"
+# arch = ["x86_64", "aarch64"]
+# distro = ["debian", "ubuntu"]
+# min_version = "11"
+
+# 运行前需要填写的参数，以 DEPLOY_PARAM_<NAME> 环境变量传给 run.sh
+# type 可选："string", "int", "bool", "enum", "secret"
+# [[params]]
+# name = "port"
+# type = "int"
+# default = 8080
+# description = "Port to listen on"
+# pattern = "[0-9]{2,5}"
+
+# 生命周期动作及其脚本，用 `deploy run <task> <action>` 运行，install 默认是 run.sh
+# 脚本里可以通过 DEPLOY_ACTION 环境变量得知当前动作
+# [actions]
+# install = "run.sh"
+# uninstall = "uninstall.sh"
+# upgrade = "upgrade.sh"
+# verify = "verify.sh"
+# 可选的检查脚本，退出码为 0 表示本机已经应用过，`deploy get` 会跳过安装
+# check = "check.sh"
+
+# 解压后强制设置的文件权限，优先于打包时记录的权限
+# [modes]
+# "files/id_rsa" = "0600"
+"#;
     let config_content = config_content
         .replace("{uuid}", &uuid.to_string())
         .replace("{name}", name);
@@ -174,10 +589,36 @@ REM Add your task logic here
     Ok(())
 }
 
+fn generate_key(path: &str) -> anyhow::Result<()> {
+    if Path::new(path).exists() {
+        return Err(anyhow!("{} already exists", path));
+    }
+    let (secret, public) = generate_author_key();
+    fs::write(path, secret)?;
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    println!("Private key written to {}", path);
+    println!("Set `author_key = \"{}\"` in {}", path, CONFIG_PATH);
+    println!("Public key (add it to `trusted_keys` on every host):");
+    println!("{}", public.green().bold());
+    Ok(())
+}
+
 fn create_default_config() {
     let default_config = Config {
         server: "http://localhost:3000".to_string(),
-        password: "password".to_string(),
+        password: "admin:password".to_string(),
+        signing: None,
+        ca_cert: None,
+        fingerprint: None,
+        client_cert: None,
+        client_key: None,
+        author_key: None,
+        trusted_keys: HashMap::new(),
+        unpack: UnpackLimits::default(),
     };
 
     let config_dir = Path::new(CONFIG_PATH).parent().unwrap();
@@ -194,116 +635,430 @@ fn read_config() -> anyhow::Result<Config> {
     Ok(config)
 }
 
-fn list_tasks(client: &Client, config: &Config) -> anyhow::Result<()> {
+/// 根据配置创建 HTTP 客户端，配置了 CA 证书包、证书指纹或客户端证书时使用自定义的 TLS 配置
+fn build_client(config: &Config) -> anyhow::Result<Client> {
+    let mut builder = Client::builder();
+    let client_auth = match (&config.client_cert, &config.client_key) {
+        (Some(cert), Some(key)) => Some((Path::new(cert), Path::new(key))),
+        (None, None) => None,
+        _ => return Err(anyhow!("client_cert and client_key must be set together")),
+    };
+    if config.ca_cert.is_some() || config.fingerprint.is_some() || client_auth.is_some() {
+        let tls = client_tls_config(
+            config.ca_cert.as_deref().map(Path::new),
+            config.fingerprint.as_deref(),
+            client_auth,
+        )?;
+        builder = builder.use_preconfigured_tls(tls);
+    }
+    Ok(builder.build()?)
+}
+
+/// 发送请求并附加认证信息：配置了签名密钥时签名请求，否则携带明文令牌
+fn send(client: &Client, config: &Config, builder: RequestBuilder) -> anyhow::Result<Response> {
+    let mut request = builder.build()?;
+    let authorization = match &config.signing {
+        Some(signing) => {
+            let body = match request.body_mut() {
+                Some(body) => body.buffer()?.to_vec(),
+                None => Vec::new(),
+            };
+            let url = request.url();
+            let path = match url.query() {
+                Some(query) => format!("{}?{}", url.path(), query),
+                None => url.path().to_string(),
+            };
+            SignedHeader::sign(
+                &signing.token,
+                &signing.key,
+                request.method().as_str(),
+                &path,
+                &body,
+            )
+            .to_string()
+        }
+        None => config.password.clone(),
+    };
+    request
+        .headers_mut()
+        .insert(AUTHORIZATION, HeaderValue::from_str(&authorization)?);
+    let response = client.execute(request)?;
+    // 被限速或因多次认证失败被锁定
+    if response.status() == StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("a few");
+        return Err(anyhow!("Too many requests, retry after {} seconds", retry_after));
+    }
+    Ok(response)
+}
+
+fn list_tasks(client: &Client, config: &Config, query: &TaskQuery) -> anyhow::Result<()> {
     let url = format!("{}/tasks", config.server);
-    let response = client
-        .get(&url)
-        .header("Authorization", &config.password)
-        .send();
+    let fetch = |builder: RequestBuilder| -> anyhow::Result<Vec<Task>> {
+        let resp = send(client, config, builder)?;
+        if !resp.status().is_success() {
+            return Err(anyhow!("{}", resp.text()?));
+        }
+        resp.json().map_err(|e| anyhow!("Failed to get tasks: {}", e))
+    };
+    let tasks = fetch(client.get(&url).query(query))?;
+    if tasks.is_empty() {
+        println!("No tasks found.");
+    }
+    let host = HostFacts::detect();
+    for task in &tasks {
+        // 显示 uuid 前 8 位，任务重名时可以用它指定任务
+        let short_uuid = &task.uuid[..task.uuid.len().min(8)];
+        let mut labels = Vec::new();
+        if let Some(category) = &task.category {
+            labels.push(category.yellow().to_string());
+        }
+        labels.extend(task.tags.iter().map(|t| format!("#{}", t).magenta().to_string()));
+        // 不支持本机平台的任务淡化显示并给出原因
+        match task.platform.as_ref().map(|p| p.check(&host)) {
+            Some(Err(reasons)) => println!(
+                "{} {} - {} {} {}",
+                short_uuid.dimmed(),
+                task.name.dimmed(),
+                task.description.dimmed(),
+                labels.join(" "),
+                format!("(incompatible: {})", reasons.join("; ")).red()
+            ),
+            _ => println!(
+                "{} {} - {} {}",
+                short_uuid.blue().bold(),
+                task.name.cyan(),
+                task.description.custom_color((192, 192, 192)),
+                labels.join(" ")
+            ),
+        }
+    }
+    Ok(())
+}
 
-    match response {
-        Ok(resp) => {
-            if resp.status().is_success() {
-                let tasks: Vec<Task> = resp
-                    .json()
-                    .map_err(|e| anyhow::anyhow!("Failed to get tasks: {}", e))?;
-                for (i, task) in tasks.iter().enumerate() {
-                    println!(
-                        "{}: {} - {}",
-                        i.to_string().blue().bold(),
-                        task.name.cyan(),
-                        task.description.custom_color((192, 192, 192))
-                    );
-                }
-            } else {
-                eprintln!("Error: {}", resp.text()?);
-            }
+/// 运行任务时的选项，版本和参数只作用于指定的任务，不作用于它的依赖
+struct GetOptions {
+    version: Option<u32>,
+    provided: HashMap<String, String>,
+    no_deps: bool,
+    /// 不满足平台要求时仍然运行
+    force: bool,
+    /// 要运行的动作，依赖总是运行 install
+    action: String,
+}
+
+fn get_task(
+    client: &Client,
+    config: &Config,
+    reference: &str,
+    options: &GetOptions,
+) -> anyhow::Result<()> {
+    let tasks = fetch_tasks(client, config)?;
+    let task = find_task(&tasks, reference)?;
+    run_task(client, config, &tasks, task, options)
+}
+
+/// 运行任务，tasks 是服务端的完整任务列表，用来计算依赖
+fn run_task(
+    client: &Client,
+    config: &Config,
+    tasks: &[Task],
+    task: &Task,
+    options: &GetOptions,
+) -> anyhow::Result<()> {
+    // 先按拓扑顺序运行依赖，已经在本机应用过且内容没变的依赖会跳过
+    let order = if options.no_deps {
+        vec![task.clone()]
+    } else {
+        dependency_order(tasks, task)?
+    };
+    if order.len() > 1 {
+        let names: Vec<_> = order.iter().map(|t| t.name.as_str()).collect();
+        println!("Dependency order: {}", names.join(" -> ").bold());
+    }
+    let mut applied = read_applied()?;
+    let dependency_options = GetOptions {
+        version: None,
+        provided: HashMap::new(),
+        no_deps: false,
+        force: options.force,
+        action: DEFAULT_ACTION.to_string(),
+    };
+    for dependency in &order[..order.len() - 1] {
+        apply_task(client, config, dependency, &dependency_options, &mut applied, true)?;
+    }
+    apply_task(client, config, task, options, &mut applied, false)
+}
+
+/// 下载任务包并解压到 /tmp，校验签名后返回解压目录和包里的 config.toml
+fn fetch_package(
+    client: &Client,
+    config: &Config,
+    task: &Task,
+    version: Option<u32>,
+) -> anyhow::Result<(String, Task)> {
+    // 首先检查是否有缓存的任务压缩包
+    // 服务端返回的任务标识同样要校验，防止拼出 /tmp 以外的路径
+    let task_id = task.id()?;
+    let cache_path = format!("/tmp/{}.zip", task_id);
+    let md5 = md5::compute(fs::read(&cache_path).unwrap_or_else(|_| {
+        println!("Failed to read cached file\nStarting to download...");
+        vec![]
+    }));
+    let download_url = format!("{}/tasks/download", config.server);
+    let src_path = format!("{}.zip", task.name);
+    let mut file = fs::File::create(&src_path)?;
+    let mut form = vec![
+        ("uuid", task.uuid.clone()),
+        ("name", task.name.clone()),
+        ("md5", format!("{:x}", md5)),
+    ];
+    if let Some(version) = version {
+        form.push(("version", version.to_string()));
+    }
+    let mut download_resp = send(client, config, client.post(download_url).form(&form))?;
+    let mut check = true;
+    if download_resp.status() == reqwest::StatusCode::NOT_MODIFIED {
+        println!("Task {} is up to date, no need to download.", task.name);
+        check = false;
+    }
+    if check {
+        if !download_resp.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "Failed to download task {}",
+                download_resp.text()?
+            ));
         }
-        Err(err) => eprintln!("Request failed: {}", err),
+        std::io::copy(&mut download_resp, &mut file)?;
+        println!("Task downloaded: {}.zip", task.name);
+        // 压缩包拷贝到/tmp目录
+        fs::copy(&src_path, cache_path)?;
+    } else {
+        println!("Using cached task: {}", cache_path);
+        // 拷贝缓存的压缩包到当前目录
+        fs::copy(cache_path, &src_path)?;
+    }
+    // 解压到/tmp目录中
+    let dest_dir = format!("/tmp/{}", task_id);
+    // 清掉旧的解压结果，避免残留文件影响签名校验
+    if Path::new(&dest_dir).exists() {
+        fs::remove_dir_all(&dest_dir)?;
+    }
+    let unpack_res =
+        unpack_zip_with_limits(Path::new(&src_path), Path::new(&dest_dir), &config.unpack);
+    // 删除压缩包
+    fs::remove_file(src_path)?;
+    if let Err(e) = unpack_res {
+        if Path::new(&dest_dir).exists() {
+            fs::remove_dir_all(&dest_dir)?;
+        }
+        return Err(anyhow!("Failed to unpack zip file {}", e));
+    }
+    // 运行前校验作者签名，未签名或签名不可信的任务一律拒绝
+    match verify_package(Path::new(&dest_dir), &config.trusted_keys) {
+        Ok(signer) => {
+            println!("Package signature verified, signed by {}", signer.green());
+        }
+        Err(e) => {
+            return Err(anyhow!("Refusing to run task {}: {}", task.name, e));
+        }
+    }
+    // 平台要求、参数和动作都以签名过的包里的 config.toml 为准
+    let content = fs::read_to_string(Path::new(&dest_dir).join("config.toml"))?;
+    let package_task: Task = toml::from_str(&content)?;
+    // 清单里的 [modes] 经过签名，优先于压缩包里记录的权限
+    apply_modes(Path::new(&dest_dir), &package_task.modes)
+        .map_err(|e| anyhow!("Failed to set file modes of task {}: {}", task.name, e))?;
+    Ok((dest_dir, package_task))
+}
+
+/// 下载、校验并运行一个任务，脚本没有成功运行时返回错误。
+/// 给出 applied 时该任务作为依赖运行：本机已经应用过相同内容则跳过，运行成功后记录下来
+fn apply_task(
+    client: &Client,
+    config: &Config,
+    task: &Task,
+    options: &GetOptions,
+    applied: &mut HashMap<String, String>,
+    is_dependency: bool,
+) -> anyhow::Result<()> {
+    let task_id = task.id()?;
+    let (dest_dir, package_task) = fetch_package(client, config, task, options.version)?;
+    if let Some(platform) = &package_task.platform
+        && let Err(reasons) = platform.check(&HostFacts::detect())
+    {
+        if !options.force {
+            return Err(anyhow!(
+                "Task {} doesn't support this host: {}. Use --force to run it anyway",
+                task.name,
+                reasons.join("; ")
+            ));
+        }
+        eprintln!("{} {}", "Warning: running anyway,".yellow(), reasons.join("; "));
+    }
+    // 作为依赖时，本机已经应用过相同内容的任务不再运行
+    let hash = content_hash(Path::new(&dest_dir))?;
+    if is_dependency && applied.get(&task_id.to_string()) == Some(&hash) {
+        println!("Dependency {} is already applied, skipping.", task.name.green());
+        return Ok(());
+    }
+    let mut envs = resolve_params(&package_task.params, &options.provided, prompt_param)?;
+    // 密钥只作为环境变量传给脚本，不落盘
+    let secrets = fetch_secrets(client, config, task)?;
+    if !secrets.is_empty() {
+        println!("Injecting {} secret(s) into the environment", secrets.len());
+    }
+    envs.extend(secrets);
+    envs.insert("DEPLOY_ACTION".to_string(), options.action.clone());
+    // 安装前先运行检查脚本，退出码为 0 表示本机已经应用过，不再运行；不同意运行检查时直接进入安装
+    if options.action == DEFAULT_ACTION
+        && let Some(check) = package_task.check_script()
+        && confirm_check(&task.name, &Path::new(&dest_dir).join(check))?
+        && run_check(&Path::new(&dest_dir).join(check), &envs)?
+    {
+        println!("Task {} is already applied on this host, skipping.", task.name.green());
+        applied.insert(task_id.to_string(), hash);
+        write_applied(applied)?;
+        return Ok(());
     }
+    // 解压后运行动作对应的脚本，install 默认是 run.sh
+    let script = package_task.action_script(&options.action)?;
+    let script_path = Path::new(&dest_dir).join(&script);
+    if !script_path.is_file() {
+        return Err(anyhow!("Script {} of action {} not found in the package", script, options.action));
+    }
+    if !run_script(&script_path, &options.action, &envs) {
+        return Err(anyhow!("Action {} of task {} did not complete", options.action, task.name));
+    }
+    // install 和 upgrade 记录本次应用的内容，之后作为依赖时可以跳过；uninstall 后清除记录
+    match options.action.as_str() {
+        "install" | "upgrade" => {
+            applied.insert(task_id.to_string(), hash);
+        }
+        "uninstall" => {
+            applied.remove(&task_id.to_string());
+        }
+        _ => return Ok(()),
+    }
+    write_applied(applied)?;
     Ok(())
 }
 
-fn get_task_by_index(client: &Client, config: &Config, index: usize) -> anyhow::Result<()> {
-    let url = format!("{}/tasks", config.server);
-    let response = client
-        .get(&url)
-        .header("Authorization", &config.password)
-        .send();
+/// 任务在本机的应用状态
+enum Compliance {
+    /// 检查脚本返回 0，或没有检查脚本但本机记录过相同内容
+    Applied,
+    NotApplied,
+    /// 没有检查脚本，也没有应用记录
+    Unknown,
+    /// 本机不满足任务的平台要求
+    Incompatible(String),
+    /// 用户没有同意运行检查脚本
+    Declined,
+}
 
-    match response {
-        Ok(resp) => {
-            if resp.status().is_success() {
-                let tasks: Vec<Task> = resp.json()?;
-                if let Some(task) = tasks.get(index) {
-                    // 首先检查是否有缓存的任务压缩包
-                    let cache_path = format!("/tmp/{}-{}.zip", task.name, task.uuid);
-                    let md5 = md5::compute(fs::read(&cache_path).unwrap_or_else(|_| {
-                        println!("Failed to read cached file\nStarting to download...");
-                        vec![]
-                    }));
-                    let download_url = format!("{}/tasks/download", config.server);
-                    let src_path = format!("{}.zip", task.name);
-                    let mut file = fs::File::create(&src_path)?;
-                    let mut download_resp = client
-                        .post(download_url)
-                        .form(&[
-                            ("uuid", &task.uuid),
-                            ("name", &task.name),
-                            ("md5", &format!("{:x}", md5)),
-                        ])
-                        .header("Authorization", &config.password)
-                        .send()?;
-                    let mut check = true;
-                    if download_resp.status() == reqwest::StatusCode::NOT_MODIFIED {
-                        println!("Task {} is up to date, no need to download.", task.name);
-                        check = false;
-                    }
-                    if check {
-                        if !download_resp.status().is_success() {
-                            return Err(anyhow::anyhow!(
-                                "Failed to download task {}",
-                                download_resp.text()?
-                            ));
-                        }
-                        std::io::copy(&mut download_resp, &mut file)?;
-                        // let content = download_resp.bytes()?;
-                        // file.write_all(&content)?;
-                        println!("Task downloaded: {}.zip", task.name);
-                        // 压缩包拷贝到/tmp目录
-                        fs::copy(&src_path, cache_path)?;
-                    } else {
-                        println!("Using cached task: {}", cache_path);
-                        // 拷贝缓存的压缩包到当前目录
-                        fs::copy(cache_path, &src_path)?;
-                    }
-                    // 解压到/tmp目录中
-                    let dest_dir = format!("/tmp/{}-{}", task.name, task.uuid);
-                    let unpack_res = unpack_zip(Path::new(&src_path), Path::new(&dest_dir));
-                    // 删除压缩包
-                    fs::remove_file(src_path).unwrap();
-                    // 解压后运行其中的run.sh脚本
-                    if unpack_res.is_ok() {
-                        #[cfg(target_family = "unix")]
-                        {
-                            let script_path = Path::new(&dest_dir).join("run.sh");
-                            run_script(&script_path);
-                        }
-                    } else {
-                        eprintln!(
-                            "Error: Failed to unpack zip file {}",
-                            unpack_res.unwrap_err()
-                        );
-                    }
-                } else {
-                    eprintln!("Error: Task index out of range.");
-                }
-            } else {
-                eprintln!("Error: {:#?}", resp.json::<Value>());
+/// 运行检查脚本前显示脚本内容并等待确认，与 run_script 一样输入 y 才运行
+fn confirm_check(task: &str, script_path: &Path) -> anyhow::Result<bool> {
+    println!("{} {} ({})", "Check script of".green().bold(), task.bold(), script_path.display());
+    println!("{}", fs::read_to_string(script_path)?);
+    print!("{}", "Do you want to run this check script? (y/n) ".green().bold());
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    Ok(input.trim().eq_ignore_ascii_case("y"))
+}
+
+/// 检查指定任务或所有任务是否已经在本机应用，有任务未应用或检查失败时返回错误。
+/// yes 为 false 时每个检查脚本运行前都要确认
+fn check_tasks(client: &Client, config: &Config, reference: Option<&str>, yes: bool) -> anyhow::Result<()> {
+    let tasks = fetch_tasks(client, config)?;
+    let selected = match reference {
+        Some(reference) => vec![find_task(&tasks, reference)?],
+        None => tasks.iter().collect(),
+    };
+    let host = HostFacts::detect();
+    let applied = read_applied()?;
+    let mut failed = 0;
+    for task in selected {
+        let status = match check_task(client, config, task, &host, &applied, yes) {
+            Ok(Compliance::Applied) => "applied".green(),
+            Ok(Compliance::NotApplied) => {
+                failed += 1;
+                "not applied".red()
+            }
+            Ok(Compliance::Unknown) => "unknown, no check script".yellow(),
+            Ok(Compliance::Incompatible(reason)) => format!("skipped, {}", reason).dimmed(),
+            Ok(Compliance::Declined) => "skipped, check script not confirmed".yellow(),
+            Err(e) => {
+                failed += 1;
+                format!("check failed: {}", e).red()
             }
+        };
+        println!("{} {}", task.name.cyan(), status);
+    }
+    if failed > 0 {
+        return Err(anyhow!("{} task(s) are not applied on this host", failed));
+    }
+    Ok(())
+}
+
+/// 下载任务包并运行检查脚本，参数只使用默认值，不询问
+fn check_task(
+    client: &Client,
+    config: &Config,
+    task: &Task,
+    host: &HostFacts,
+    applied: &HashMap<String, String>,
+    yes: bool,
+) -> anyhow::Result<Compliance> {
+    if let Some(platform) = &task.platform
+        && let Err(reasons) = platform.check(host)
+    {
+        return Ok(Compliance::Incompatible(reasons.join("; ")));
+    }
+    let (dest_dir, package_task) = fetch_package(client, config, task, None)?;
+    let Some(check) = package_task.check_script() else {
+        let hash = content_hash(Path::new(&dest_dir))?;
+        return Ok(if applied.get(&task.id()?.to_string()) == Some(&hash) {
+            Compliance::Applied
+        } else {
+            Compliance::Unknown
+        });
+    };
+    let mut envs = resolve_params(&package_task.params, &HashMap::new(), |param, error| {
+        match (&param.default, error) {
+            (_, Some(e)) => Err(anyhow!("{}", e)),
+            (Some(_), None) => Ok(None),
+            (None, None) => Err(anyhow!("parameter {} has no default", param.name)),
         }
-        Err(err) => eprintln!("Request failed: {}", err),
+    })?;
+    envs.extend(fetch_secrets(client, config, task)?);
+    envs.insert("DEPLOY_ACTION".to_string(), CHECK_ACTION.to_string());
+    if !yes && !confirm_check(&task.name, &Path::new(&dest_dir).join(check))? {
+        return Ok(Compliance::Declined);
+    }
+    Ok(if run_check(&Path::new(&dest_dir).join(check), &envs)? {
+        Compliance::Applied
+    } else {
+        Compliance::NotApplied
+    })
+}
+
+/// 记录本机已应用任务的文件，任务标识 -> 内容哈希
+const APPLIED_PATH: &str = "/etc/deploycli/applied.json";
+
+fn read_applied() -> anyhow::Result<HashMap<String, String>> {
+    if !Path::new(APPLIED_PATH).exists() {
+        return Ok(HashMap::new());
     }
+    Ok(serde_json::from_str(&fs::read_to_string(APPLIED_PATH)?)?)
+}
+
+fn write_applied(applied: &HashMap<String, String>) -> anyhow::Result<()> {
+    fs::write(APPLIED_PATH, serde_json::to_string_pretty(applied)?)?;
     Ok(())
 }
 
@@ -317,39 +1072,37 @@ fn upload_task(client: &Client, config: &Config, path: &str) -> anyhow::Result<(
     if !config_path.exists() {
         return Err(anyhow!("config.toml not found in the task directory"));
     }
-    // 读取config.toml文件中的name值
-    let config_content = fs::read_to_string(&config_path)?;
-    let task: Task = toml::from_str(&config_content)?;
-    // 至少要有run.sh或者run.bat脚本
-    let script_path = if cfg!(target_family = "unix") {
-        file_path.join("run.sh")
-    } else {
-        file_path.join("run.bat")
-    };
-    if !script_path.exists() {
-        return Err(anyhow!("run.sh or run.bat not found in the task directory"));
-    }
+    // 与服务端相同的清单校验，同时检查平台需要的脚本和声明的动作脚本都存在
+    let task = load_manifest(file_path).map_err(|e| anyhow!("Invalid task:\n{}", e))?;
+    // 用作者私钥对任务清单签名
+    let author_key = config
+        .author_key
+        .as_ref()
+        .ok_or(anyhow!("author_key is not configured, generate one with `deploy keygen`"))?;
+    let secret = fs::read_to_string(author_key)?;
+    let signature = sign_package(file_path, &secret)?;
     // 压缩成zip
     let zip_path = format!("{}.zip", task.name);
     create_zip(file_path, Path::new(&zip_path))?;
     let url = format!("{}/tasks/upload", config.server);
     let file = fs::File::open(&zip_path)?;
-    let filename = format!("{}-{}", task.name, task.uuid);
+    let filename = task.id()?.dir_name();
     let part = multipart::Part::reader(file)
         .file_name(filename)
         .mime_str("application/zip")?;
-    let form = multipart::Form::new().part("file", part);
-    let response = client
-        .post(&url)
-        .header("Authorization", &config.password)
-        .multipart(form)
-        .send();
+    let form = multipart::Form::new()
+        .part("file", part)
+        .text("signature", serde_json::to_string(&signature)?);
+    let response = send(client, config, client.post(&url).multipart(form));
     // 删掉临时文件
     fs::remove_file(&zip_path).unwrap();
     match response {
         Ok(resp) => {
             if resp.status().is_success() {
-                println!("Task uploaded successfully.");
+                println!(
+                    "Task uploaded successfully, signed by {}.",
+                    author_public_key(&secret)?
+                );
             } else {
                 eprintln!("Error: {:#?}", resp.json::<Value>());
             }
@@ -359,27 +1112,61 @@ fn upload_task(client: &Client, config: &Config, path: &str) -> anyhow::Result<(
     Ok(())
 }
 
-fn delete_task(client: &Client, config: &Config, index: usize) -> anyhow::Result<()> {
-    let tasks: Vec<Task> = client
-        .get(format!("{}/tasks", config.server))
-        .header("Authorization", &config.password)
-        .send()?
-        .json()?;
-    if index >= tasks.len() {
-        return Err(anyhow!("Task index out of range"));
-    }
-    let task = &tasks[index];
-    let url = format!("{}/tasks/delete", config.server);
-    // Post请求删除任务
-    let resp = client
-        .post(&url)
-        .header("Authorization", &config.password)
-        .form(&[("uuid", &task.uuid), ("name", &task.name)])
-        .send()?;
+/// 校验任务目录并列出 `deploy post` 会上传的条目和文件总大小，不签名也不上传
+fn preview_upload(path: &str) -> anyhow::Result<()> {
+    let dir = Path::new(path);
+    let task = load_manifest(dir).map_err(|e| anyhow!("Invalid task:\n{}", e))?;
+    let mut files = 0;
+    let mut total: u64 = 0;
+    for (name, entry) in package_entries(dir)? {
+        match entry {
+            PackageEntry::File(file) => {
+                let size = fs::metadata(file)?.len();
+                files += 1;
+                total += size;
+                println!("{:>12}  {}", size, name);
+            }
+            PackageEntry::Dir => println!("{:>12}  {}/", "", name),
+            PackageEntry::Symlink(target) => println!("{:>12}  {} -> {}", "", name, target.display()),
+        }
+    }
+    for name in ignored_entries(dir)? {
+        println!("{:>12}  {}", "ignored".dimmed(), name.dimmed());
+    }
+    println!(
+        "{} {} file(s), {} bytes would be uploaded",
+        task.name.bold(),
+        files,
+        total
+    );
+    Ok(())
+}
+
+/// 用与服务端相同的规则校验任务目录，逐条打印问题
+fn lint_task(path: &str) -> anyhow::Result<()> {
+    match load_manifest(Path::new(path)) {
+        Ok(task) => {
+            println!("{} {} is valid", "ok:".green().bold(), task.name);
+            Ok(())
+        }
+        Err(e) => {
+            for diagnostic in &e.0 {
+                eprintln!("{} {}", "error:".red().bold(), diagnostic);
+            }
+            Err(anyhow!("{} problem(s) found in {}", e.0.len(), path))
+        }
+    }
+}
+
+fn delete_task(client: &Client, config: &Config, reference: &str) -> anyhow::Result<()> {
+    let task = resolve_task(client, config, reference)?;
+    // 按 `<name>-<uuid>` 删除，不会因为列表变化删错任务
+    let url = format!("{}/tasks/{}", config.server, task.id()?);
+    let resp = send(client, config, client.delete(&url))?;
     if resp.status().is_success() {
         println!("Task {} deleted successfully.", task.name);
         // 删除缓存
-        clean_cache(client, config, Some(index))?;
+        remove_cache(&task)?;
     } else {
         eprintln!("Error: {:#?}", resp.json::<Value>());
     }
@@ -388,11 +1175,9 @@ fn delete_task(client: &Client, config: &Config, index: usize) -> anyhow::Result
 
 fn update_database(client: &Client, config: &Config) -> anyhow::Result<()> {
     let url = format!("{}/tasks/update", config.server);
-    let resp = client
-        .get(&url)
-        .header("Authorization", &config.password)
-        .send()?;
+    let resp = send(client, config, client.get(&url))?;
     if resp.status().is_success() {
+        print_report(&resp.json()?);
         println!("Database updated successfully.");
     } else {
         eprintln!("Error: {:#?}", resp.json::<Value>());
@@ -400,37 +1185,424 @@ fn update_database(client: &Client, config: &Config) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn clean_cache(client: &Client, config: &Config, index: Option<usize>) -> anyhow::Result<()> {
-    let mut tasks: Vec<Task> = client
-        .get(format!("{}/tasks", config.server))
-        .header("Authorization", &config.password)
-        .send()?
-        .json()?;
-    if index.is_some() && index.unwrap() >= tasks.len() {
-        return Err(anyhow!("Task index out of range"));
-    }
-    if index.is_some() {
-        // tasks里只保留index的任务
-        let index = index.unwrap();
-        tasks = vec![tasks[index].clone()];
-    }
-    // 删除所有缓存
-    for task in tasks {
-        let cache_path = format!("/tmp/{}-{}", task.name, task.uuid);
-        let cache_path = Path::new(&cache_path);
-        if cache_path.exists() {
-            fs::remove_dir_all(cache_path)?;
-            println!("Cache for task {} deleted successfully.", task.name);
-        } else {
-            println!("No cache found for task {}.", task.name);
+fn import_tasks(client: &Client, config: &Config) -> anyhow::Result<()> {
+    let url = format!("{}/tasks/import", config.server);
+    let resp = send(client, config, client.post(&url))?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("{:#?}", resp.json::<Value>()));
+    }
+    let report: Value = resp.json()?;
+    print_report(&report);
+    if report["imported"].as_array().is_none_or(|imported| imported.is_empty()) {
+        println!("Nothing new to import.");
+    }
+    Ok(())
+}
+
+/// 打印服务端重建或导入的结果：导入的版本和跳过的任务
+fn print_report(report: &Value) {
+    let lines = |key: &str| -> Vec<String> {
+        report[key]
+            .as_array()
+            .map(|items| items.iter().filter_map(|i| i.as_str().map(str::to_string)).collect())
+            .unwrap_or_default()
+    };
+    for line in lines("imported") {
+        println!("{} {}", "imported:".green().bold(), line);
+    }
+    for line in lines("skipped") {
+        eprintln!("{} {}", "skipped:".yellow().bold(), line);
+    }
+}
+
+fn collect_garbage(client: &Client, config: &Config) -> anyhow::Result<()> {
+    let url = format!("{}/blobs/gc", config.server);
+    let resp = send(client, config, client.post(&url))?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("{:#?}", resp.json::<Value>()));
+    }
+    let report: GcReport = resp.json()?;
+    println!(
+        "Removed {} blob(s), freed {} bytes. Kept {} blob(s), {} bytes, for {} file reference(s).",
+        report.removed, report.freed_bytes, report.kept, report.kept_bytes, report.references
+    );
+    Ok(())
+}
+
+/// 合并 `--set key=value` 和参数文件给出的值，命令行优先
+fn provided_params(set: &[String], values: Option<&str>) -> anyhow::Result<HashMap<String, String>> {
+    let mut provided = HashMap::new();
+    if let Some(path) = values {
+        let file: HashMap<String, ParamValue> = toml::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| anyhow!("Failed to parse {}: {}", path, e))?;
+        provided.extend(file.into_iter().map(|(k, v)| (k, v.to_string())));
+    }
+    for pair in set {
+        let (key, value) = pair
+            .split_once('=')
+            .ok_or(anyhow!("Expected KEY=VALUE, got {}", pair))?;
+        provided.insert(key.to_string(), value.to_string());
+    }
+    Ok(provided)
+}
+
+/// 在终端询问参数的值，直接回车时返回 None 使用默认值。上一次输入不合法时先显示原因
+fn prompt_param(param: &Param, error: Option<&ParamError>) -> anyhow::Result<Option<String>> {
+    if let Some(e) = error {
+        eprintln!("{}", e.to_string().red());
+    }
+    let mut label = param.name.green().bold().to_string();
+    if !param.description.is_empty() {
+        label = format!("{} ({})", label, param.description);
+    }
+    if !param.values.is_empty() {
+        label = format!("{} [{}]", label, param.values.join("/"));
+    }
+    if let Some(default) = &param.default {
+        label = format!("{} [default: {}]", label, default);
+    }
+    let input = if param.kind == ParamKind::Secret {
+        rpassword::prompt_password(format!("{}: ", label))?
+    } else {
+        print!("{}: ", label);
+        std::io::stdout().flush()?;
+        let mut input = String::new();
+        // stdin 已关闭时不能再询问，否则没有默认值的参数会一直重试
+        if std::io::stdin().read_line(&mut input)? == 0 {
+            return Err(anyhow!("stdin is closed, cannot read parameter {}", param.name));
         }
-        let cache_zip = cache_path.with_extension("zip");
-        if cache_zip.exists() {
-            fs::remove_file(&cache_zip)?;
-            println!("Cache zip for task {} deleted successfully.", task.name);
-        } else {
-            println!("No cache zip found for task {}.", task.name);
+        input.trim_end_matches(['\r', '\n']).to_string()
+    };
+    Ok(if input.is_empty() { None } else { Some(input) })
+}
+
+/// 获取服务端的完整任务列表
+fn fetch_tasks(client: &Client, config: &Config) -> anyhow::Result<Vec<Task>> {
+    let resp = send(client, config, client.get(format!("{}/tasks", config.server)))?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("Failed to list tasks {}", resp.text()?));
+    }
+    Ok(resp.json()?)
+}
+
+/// 按任务名、完整 uuid 或唯一的 uuid 前缀查找任务
+fn resolve_task(client: &Client, config: &Config, reference: &str) -> anyhow::Result<Task> {
+    let tasks = fetch_tasks(client, config)?;
+    Ok(find_task(&tasks, reference)?.clone())
+}
+
+fn list_versions(client: &Client, config: &Config, reference: &str) -> anyhow::Result<()> {
+    let task = resolve_task(client, config, reference)?;
+    let url = format!("{}/tasks/{}/versions", config.server, task.id()?);
+    let resp = send(client, config, client.get(&url))?;
+    if !resp.status().is_success() {
+        eprintln!("Error: {:#?}", resp.json::<Value>());
+        return Ok(());
+    }
+    let versions: Vec<TaskVersion> = resp.json()?;
+    if versions.is_empty() {
+        println!("No versions recorded for task {}.", task.name);
+    }
+    let latest = versions.last().map(|v| v.version);
+    for version in &versions {
+        let note = match version.rollback_of {
+            Some(target) => format!(" (rollback to v{})", target),
+            None => String::new(),
+        };
+        let marker = if Some(version.version) == latest { " latest".green().to_string() } else { String::new() };
+        println!(
+            "v{:<4} {} {} by {}{}{}",
+            version.version,
+            format_timestamp(version.timestamp),
+            &version.hash[..version.hash.len().min(12)],
+            version.actor.cyan(),
+            note,
+            marker
+        );
+    }
+    Ok(())
+}
+
+fn rollback_task(client: &Client, config: &Config, reference: &str, version: u32) -> anyhow::Result<()> {
+    let task = resolve_task(client, config, reference)?;
+    let url = format!("{}/tasks/{}/rollback", config.server, task.id()?);
+    let resp = send(
+        client,
+        config,
+        client.post(&url).form(&[("version", version.to_string())]),
+    )?;
+    if resp.status().is_success() {
+        println!("{}", resp.json::<String>()?);
+    } else {
+        eprintln!("Error: {:#?}", resp.json::<Value>());
+    }
+    Ok(())
+}
+
+fn fetch_secrets(client: &Client, config: &Config, task: &Task) -> anyhow::Result<HashMap<String, String>> {
+    let url = format!("{}/tasks/secrets/fetch", config.server);
+    let resp = send(
+        client,
+        config,
+        client.post(&url).form(&[("uuid", &task.uuid), ("name", &task.name)]),
+    )?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("Failed to fetch secrets: {:?}", resp.json::<Value>()));
+    }
+    Ok(resp.json()?)
+}
+
+fn manage_secrets(client: &Client, config: &Config, command: SecretCommands) -> anyhow::Result<()> {
+    match command {
+        SecretCommands::Set { task, name, value } => {
+            if !is_valid_secret_name(&name) {
+                return Err(anyhow!("Invalid secret name {}, use letters, digits and `_`", name));
+            }
+            let task = resolve_task(client, config, &task)?;
+            // 不在命令行里给出值时从 stdin 读取，避免留在 shell 历史里
+            let value = match value {
+                Some(value) => value,
+                None => {
+                    println!("Enter the value of {}:", name);
+                    let mut input = String::new();
+                    std::io::stdin().read_line(&mut input)?;
+                    input.trim_end_matches(['\r', '\n']).to_string()
+                }
+            };
+            let url = format!("{}/tasks/secrets/set", config.server);
+            let resp = send(
+                client,
+                config,
+                client.post(&url).form(&[
+                    ("uuid", task.uuid.as_str()),
+                    ("name", task.name.as_str()),
+                    ("secret", name.as_str()),
+                    ("value", value.as_str()),
+                ]),
+            )?;
+            if resp.status().is_success() {
+                println!("Secret {} set for task {}.", name.green(), task.name);
+            } else {
+                eprintln!("Error: {:#?}", resp.json::<Value>());
+            }
+        }
+        SecretCommands::List { task } => {
+            let task = resolve_task(client, config, &task)?;
+            let url = format!("{}/tasks/secrets/list", config.server);
+            let resp = send(
+                client,
+                config,
+                client.post(&url).form(&[("uuid", &task.uuid), ("name", &task.name)]),
+            )?;
+            if !resp.status().is_success() {
+                eprintln!("Error: {:#?}", resp.json::<Value>());
+                return Ok(());
+            }
+            let secrets: Vec<SecretInfo> = resp.json()?;
+            if secrets.is_empty() {
+                println!("No secrets for task {}.", task.name);
+            }
+            for secret in secrets {
+                println!("{}  (updated {})", secret.name.green(), format_timestamp(secret.updated_at));
+            }
+        }
+        SecretCommands::Rm { task, name } => {
+            let task = resolve_task(client, config, &task)?;
+            let url = format!("{}/tasks/secrets/delete", config.server);
+            let resp = send(
+                client,
+                config,
+                client.post(&url).form(&[
+                    ("uuid", task.uuid.as_str()),
+                    ("name", task.name.as_str()),
+                    ("secret", name.as_str()),
+                ]),
+            )?;
+            if resp.status().is_success() {
+                println!("Secret {} removed from task {}.", name, task.name);
+            } else {
+                eprintln!("Error: {:#?}", resp.json::<Value>());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn fetch_profile(client: &Client, config: &Config, name: &str) -> anyhow::Result<Profile> {
+    let url = format!("{}/profiles/{}", config.server, name);
+    let resp = send(client, config, client.get(&url))?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("{}", resp.text()?));
+    }
+    Ok(resp.json()?)
+}
+
+fn manage_profiles(client: &Client, config: &Config, command: ProfileCommands) -> anyhow::Result<()> {
+    match command {
+        ProfileCommands::List => {
+            let url = format!("{}/profiles", config.server);
+            let resp = send(client, config, client.get(&url))?;
+            if !resp.status().is_success() {
+                eprintln!("Error: {:#?}", resp.json::<Value>());
+                return Ok(());
+            }
+            let profiles: Vec<Profile> = resp.json()?;
+            if profiles.is_empty() {
+                println!("No profiles found.");
+            }
+            for profile in profiles {
+                println!(
+                    "{} - {} ({} tasks)",
+                    profile.name.cyan(),
+                    profile.description.custom_color((192, 192, 192)),
+                    profile.tasks.len()
+                );
+            }
+        }
+        ProfileCommands::Show { name } => {
+            let profile = fetch_profile(client, config, &name)?;
+            println!("{} - {}", profile.name.cyan().bold(), profile.description);
+            for (i, member) in profile.tasks.iter().enumerate() {
+                let params: Vec<String> = member
+                    .params
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect();
+                println!("{}. {} {}", i + 1, member.task, params.join(" ").dimmed());
+            }
         }
+        ProfileCommands::Set { path } => {
+            let profile: Profile = toml::from_str(&fs::read_to_string(&path)?)?;
+            let url = format!("{}/profiles", config.server);
+            let resp = send(client, config, client.post(&url).json(&profile))?;
+            if resp.status().is_success() {
+                println!("Profile {} saved.", profile.name.green());
+            } else {
+                eprintln!("Error: {:#?}", resp.json::<Value>());
+            }
+        }
+        ProfileCommands::Rm { name } => {
+            let url = format!("{}/profiles/{}", config.server, name);
+            let resp = send(client, config, client.delete(&url))?;
+            if resp.status().is_success() {
+                println!("Profile {} removed.", name);
+            } else {
+                eprintln!("Error: {:#?}", resp.json::<Value>());
+            }
+        }
+        ProfileCommands::Apply { name, force, keep_going } => {
+            apply_profile(client, config, &name, force, keep_going)?;
+        }
+    }
+    Ok(())
+}
+
+/// 按顺序运行 profile 里的任务，默认在第一个失败后停止，最后打印每个任务的结果
+fn apply_profile(client: &Client, config: &Config, name: &str, force: bool, keep_going: bool) -> anyhow::Result<()> {
+    let profile = fetch_profile(client, config, name)?;
+    let tasks = fetch_tasks(client, config)?;
+    let mut results = Vec::new();
+    let mut failed = 0;
+    for member in &profile.tasks {
+        if failed > 0 && !keep_going {
+            results.push((member.task.clone(), "skipped".yellow()));
+            continue;
+        }
+        println!("{} {}", "==>".blue().bold(), member.task.bold());
+        let options = GetOptions {
+            version: None,
+            provided: member.provided(),
+            no_deps: false,
+            force,
+            action: DEFAULT_ACTION.to_string(),
+        };
+        let result = find_task(&tasks, &member.task)
+            .map_err(anyhow::Error::from)
+            .and_then(|task| run_task(client, config, &tasks, task, &options));
+        match result {
+            Ok(()) => results.push((member.task.clone(), "passed".green())),
+            Err(e) => {
+                failed += 1;
+                eprintln!("{} {}", "Error:".red().bold(), e);
+                results.push((member.task.clone(), format!("failed: {}", e).red()));
+            }
+        }
+    }
+    println!("{} {}", "Summary of profile".bold(), profile.name.cyan().bold());
+    for (task, result) in &results {
+        println!("  {:<24} {}", task, result);
+    }
+    if failed > 0 {
+        return Err(anyhow!("{} of {} task(s) failed", failed, profile.tasks.len()));
+    }
+    Ok(())
+}
+
+fn list_audit(client: &Client, config: &Config, filter: &AuditFilter) -> anyhow::Result<()> {
+    let url = format!("{}/audit", config.server);
+    let resp = send(client, config, client.get(&url).query(filter))?;
+    if !resp.status().is_success() {
+        eprintln!("Error: {:#?}", resp.json::<Value>());
+        return Ok(());
+    }
+    let records: Vec<AuditRecord> = resp.json()?;
+    if records.is_empty() {
+        println!("No audit records found.");
+    }
+    for record in records {
+        let outcome = if record.success {
+            record.status.to_string().green()
+        } else {
+            record.status.to_string().red()
+        };
+        let short = |hash: &Option<String>| {
+            hash.as_deref()
+                .map(|h| h.chars().take(12).collect::<String>())
+                .unwrap_or_else(|| "-".to_string())
+        };
+        println!(
+            "{} {} {:<6} {} {} -> {} by {} from {}",
+            format_timestamp(record.timestamp),
+            outcome,
+            record.action,
+            record.task.as_deref().unwrap_or("-").bold(),
+            short(&record.previous_hash),
+            short(&record.new_hash),
+            record.actor.cyan(),
+            record.source_ip
+        );
+    }
+    Ok(())
+}
+
+fn clean_cache(client: &Client, config: &Config, reference: Option<&str>) -> anyhow::Result<()> {
+    let tasks = match reference {
+        Some(reference) => vec![resolve_task(client, config, reference)?],
+        // 删除所有缓存
+        None => fetch_tasks(client, config)?,
+    };
+    for task in &tasks {
+        remove_cache(task)?;
+    }
+    Ok(())
+}
+
+/// 删除任务在 /tmp 下的解压目录和压缩包缓存
+fn remove_cache(task: &Task) -> anyhow::Result<()> {
+    let cache_path = format!("/tmp/{}", task.id()?);
+    let cache_path = Path::new(&cache_path);
+    if cache_path.exists() {
+        fs::remove_dir_all(cache_path)?;
+        println!("Cache for task {} deleted successfully.", task.name);
+    } else {
+        println!("No cache found for task {}.", task.name);
+    }
+    let cache_zip = cache_path.with_extension("zip");
+    if cache_zip.exists() {
+        fs::remove_file(&cache_zip)?;
+        println!("Cache zip for task {} deleted successfully.", task.name);
+    } else {
+        println!("No cache zip found for task {}.", task.name);
     }
     Ok(())
 }
@@ -0,0 +1,286 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use uuid::Uuid;
+
+use crate::package::{PackageEntry, package_entries};
+use crate::utils::{create_symlink, file_mode, file_mtime, is_plain_relative, set_mode};
+
+/// 任务一个版本的内容：每个条目的包内路径和属性，文件内容按 SHA-256 另外存成 blob
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub entries: Vec<SnapshotEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    /// 包内路径，以 `/` 分隔
+    pub path: String,
+    #[serde(flatten)]
+    pub kind: EntryKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum EntryKind {
+    /// 文件内容的 SHA-256、字节数、权限和修改时间
+    File { blob: String, size: u64, mode: u32, mtime: u64 },
+    Dir { mode: u32, mtime: u64 },
+    Symlink { target: String },
+}
+
+impl Snapshot {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        serde_json::from_slice(&fs::read(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// 先写临时文件再改名，中途失败不会留下不完整的快照
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(tmp, path)
+    }
+
+    /// 引用的 blob，同一个 blob 被多个文件引用时出现多次
+    pub fn blobs(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().filter_map(|entry| match &entry.kind {
+            EntryKind::File { blob, .. } => Some(blob.as_str()),
+            _ => None,
+        })
+    }
+
+    /// 所有文件的总字节数
+    pub fn size(&self) -> u64 {
+        self.entries
+            .iter()
+            .map(|entry| match entry.kind {
+                EntryKind::File { size, .. } => size,
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+/// 一次回收的结果
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct GcReport {
+    /// 所有快照对 blob 的引用总数
+    pub references: usize,
+    /// 仍被引用、保留下来的 blob 数和字节数
+    pub kept: usize,
+    pub kept_bytes: u64,
+    /// 没有引用、被删除的 blob 数和字节数
+    pub removed: usize,
+    pub freed_bytes: u64,
+}
+
+fn is_blob_name(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// 按内容寻址的文件存储，blob 存放在 `<root>/<哈希前两位>/<哈希>`，相同内容只存一份
+#[derive(Debug, Clone)]
+pub struct BlobStore {
+    root: PathBuf,
+}
+
+impl BlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        BlobStore { root: root.into() }
+    }
+
+    /// blob 的路径，哈希不合法时返回错误，防止快照里的内容拼出存储以外的路径
+    pub fn path(&self, hash: &str) -> io::Result<PathBuf> {
+        if !is_blob_name(hash) {
+            return Err(invalid_data(format!("invalid blob hash `{}`", hash)));
+        }
+        Ok(self.root.join(&hash[..2]).join(hash))
+    }
+
+    /// 把文件存成 blob，已经有相同内容时直接复用，返回（哈希，字节数）
+    pub fn put(&self, file: &Path) -> io::Result<(String, u64)> {
+        let mut hasher = Sha256::new();
+        let size = io::copy(&mut fs::File::open(file)?, &mut hasher)?;
+        let hash = format!("{:x}", hasher.finalize());
+        let path = self.path(&hash)?;
+        if !path.exists() {
+            let shard = path.parent().unwrap_or(&self.root);
+            fs::create_dir_all(shard)?;
+            // 写到同目录的临时文件再改名，其它请求不会读到写了一半的 blob
+            let tmp = shard.join(format!(".{}.tmp", Uuid::new_v4()));
+            fs::copy(file, &tmp)?;
+            set_mode(&tmp, 0o644)?;
+            fs::rename(&tmp, &path)?;
+        }
+        Ok((hash, size))
+    }
+
+    /// 把目录里会打包的条目存进来，返回指向这些 blob 的快照
+    pub fn snapshot(&self, dir: &Path) -> io::Result<Snapshot> {
+        let mut entries = Vec::new();
+        for (path, entry) in package_entries(dir)? {
+            let metadata = fs::symlink_metadata(dir.join(&path))?;
+            let kind = match entry {
+                PackageEntry::File(file) => {
+                    let (blob, size) = self.put(&file)?;
+                    EntryKind::File {
+                        blob,
+                        size,
+                        mode: file_mode(&metadata),
+                        mtime: file_mtime(&metadata).unwrap_or(0),
+                    }
+                }
+                PackageEntry::Dir => EntryKind::Dir {
+                    mode: file_mode(&metadata),
+                    mtime: file_mtime(&metadata).unwrap_or(0),
+                },
+                PackageEntry::Symlink(target) => EntryKind::Symlink {
+                    target: target.to_string_lossy().to_string(),
+                },
+            };
+            entries.push(SnapshotEntry { path, kind });
+        }
+        Ok(Snapshot { entries })
+    }
+
+    /// 读取快照里某个文件的内容
+    pub fn read(&self, snapshot: &Snapshot, path: &str) -> io::Result<Vec<u8>> {
+        let blob = snapshot
+            .entries
+            .iter()
+            .find_map(|entry| match &entry.kind {
+                EntryKind::File { blob, .. } if entry.path == path => Some(blob),
+                _ => None,
+            })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} not found in snapshot", path)))?;
+        fs::read(self.path(blob)?)
+    }
+
+    /// 把快照还原成目录，恢复权限和修改时间
+    pub fn checkout(&self, snapshot: &Snapshot, dest: &Path) -> io::Result<()> {
+        fs::create_dir_all(dest)?;
+        let time = |mtime: u64| UNIX_EPOCH + Duration::from_secs(mtime);
+        // 与解压时一样，目录的权限和时间最后再设置
+        let mut dirs = Vec::new();
+        for entry in &snapshot.entries {
+            if !is_plain_relative(&entry.path) {
+                return Err(invalid_data(format!("invalid path `{}` in snapshot", entry.path)));
+            }
+            let out = dest.join(&entry.path);
+            match &entry.kind {
+                EntryKind::File { blob, mode, mtime, .. } => {
+                    if let Some(parent) = out.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::copy(self.path(blob)?, &out)?;
+                    fs::File::options().write(true).open(&out)?.set_modified(time(*mtime))?;
+                    set_mode(&out, *mode)?;
+                }
+                EntryKind::Dir { mode, mtime } => {
+                    fs::create_dir_all(&out)?;
+                    dirs.push((out, *mode, *mtime));
+                }
+                EntryKind::Symlink { target } => {
+                    if let Some(parent) = out.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    create_symlink(Path::new(target), &out)?;
+                }
+            }
+        }
+        for (path, mode, mtime) in dirs.into_iter().rev() {
+            set_mode(&path, mode)?;
+            if let Ok(dir) = fs::File::open(&path) {
+                let _ = dir.set_modified(time(mtime));
+            }
+        }
+        Ok(())
+    }
+
+    /// 按引用计数回收：统计 snapshots 对每个 blob 的引用，删除引用数为 0 的 blob。
+    /// snapshots 必须是全部快照，调用方负责保证回收期间没有新写入
+    pub fn gc(&self, snapshots: &[Snapshot]) -> io::Result<GcReport> {
+        let mut refs: HashMap<&str, usize> = HashMap::new();
+        for blob in snapshots.iter().flat_map(|s| s.blobs()) {
+            *refs.entry(blob).or_default() += 1;
+        }
+        let mut report = GcReport {
+            references: refs.values().sum(),
+            ..Default::default()
+        };
+        if !self.root.exists() {
+            return Ok(report);
+        }
+        for shard in fs::read_dir(&self.root)? {
+            let shard = shard?;
+            if !shard.file_type()?.is_dir() {
+                continue;
+            }
+            for blob in fs::read_dir(shard.path())? {
+                let blob = blob?;
+                let name = blob.file_name().to_string_lossy().to_string();
+                // 跳过正在写入的临时文件
+                if !is_blob_name(&name) {
+                    continue;
+                }
+                let size = blob.metadata()?.len();
+                if refs.contains_key(name.as_str()) {
+                    report.kept += 1;
+                    report.kept_bytes += size;
+                } else {
+                    fs::remove_file(blob.path())?;
+                    report.removed += 1;
+                    report.freed_bytes += size;
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blob_store() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let store = BlobStore::new(root.join("blobs"));
+        let src = root.join("src");
+        fs::create_dir_all(src.join("files/empty")).unwrap();
+        fs::write(src.join("run.sh"), "echo hi").unwrap();
+        fs::write(src.join("files/copy.sh"), "echo hi").unwrap();
+        fs::write(src.join("files/big.bin"), vec![7u8; 4096]).unwrap();
+        set_mode(&src.join("files/big.bin"), 0o600).unwrap();
+        let first = store.snapshot(&src).unwrap();
+        // 相同内容只存一份
+        assert_eq!(first.blobs().count(), 3);
+        assert_eq!(store.gc(std::slice::from_ref(&first)).unwrap().kept, 2);
+        assert_eq!(first.size(), 4096 + 14);
+        fs::write(src.join("run.sh"), "echo bye").unwrap();
+        let second = store.snapshot(&src).unwrap();
+        assert_eq!(String::from_utf8(store.read(&second, "run.sh").unwrap()).unwrap(), "echo bye");
+        let path = root.join("first.json");
+        first.save(&path).unwrap();
+        assert_eq!(Snapshot::load(&path).unwrap(), first);
+        let out = root.join("out");
+        store.checkout(&first, &out).unwrap();
+        assert_eq!(fs::read_to_string(out.join("run.sh")).unwrap(), "echo hi");
+        assert!(out.join("files/empty").is_dir());
+        // 还原出的目录再存一次得到同样的快照，内容、权限和时间都没有变
+        assert_eq!(store.snapshot(&out).unwrap(), first);
+        // 只剩 first 引用时回收 second 独有的 blob
+        let report = store.gc(std::slice::from_ref(&first)).unwrap();
+        assert_eq!((report.removed, report.kept, report.references), (1, 2, 3));
+        assert!(store.checkout(&second, &root.join("again")).is_err());
+    }
+}
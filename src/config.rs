@@ -1,7 +1,12 @@
-use std::{fs::File, io::Read, sync::LazyLock};
+use std::{collections::HashMap, fs::File, io::Read, sync::LazyLock};
 
 use serde::Deserialize;
 
+use deploycli::UnpackLimits;
+
+use crate::auth::Scope;
+use crate::limiter::{Lockout, Rate};
+
 const CONFIG_FILE: &str = "config.toml";
 
 pub static CFG: LazyLock<Configs> = LazyLock::new(Configs::init);
@@ -32,13 +37,87 @@ impl Configs {
 #[derive(Deserialize, Debug)]
 pub struct Configs {
     pub server: Server,
+    /// 解压上传任务包时的资源限制
+    #[serde(default)]
+    pub unpack: UnpackLimits,
+    /// 认证失败锁定和请求限速
+    #[serde(default)]
+    pub limits: Limits,
+    /// 任务密钥的加密设置
+    #[serde(default)]
+    pub secrets: Secrets,
     pub log: Log,
 }
 
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Secrets {
+    /// 加密任务密钥的主密钥文件，不存在时自动生成，务必与 tasks.db 分开备份
+    pub key_file: String,
+}
+
+impl Default for Secrets {
+    fn default() -> Self {
+        Secrets {
+            key_file: "secrets.key".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Limits {
+    pub lockout: Lockout,
+    /// 按路由模式配置的限速，如 `"/tasks/upload" = { requests = 10, per = 60 }`、`"/tasks/{id}" = ...`，
+    /// 键必须与注册的路由模式完全一致，启动时由 `check_rate_limits` 检查
+    pub rates: HashMap<String, Rate>,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Server {
     pub address: String,
-    pub password: String,
+    /// 允许访问的令牌列表
+    pub tokens: Vec<Token>,
+    /// 签名请求允许的最大时间偏差（秒），超出视为过期
+    #[serde(default = "default_signature_max_age")]
+    pub signature_max_age: u64,
+    /// 签名请求的请求体要读进内存计算哈希，这里限制其大小（字节）
+    #[serde(default = "default_max_signed_body_size")]
+    pub max_signed_body_size: usize,
+    /// 配置后直接以 HTTPS 提供服务
+    pub tls: Option<Tls>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Tls {
+    /// PEM 格式的证书链路径
+    pub cert: String,
+    /// PEM 格式的私钥路径
+    pub key: String,
+    /// 配置后要求客户端出示由该 CA 签发的证书，证书 CN 对应同名令牌
+    pub client_ca: Option<String>,
+}
+
+fn default_signature_max_age() -> u64 {
+    300
+}
+
+fn default_max_signed_body_size() -> usize {
+    64 * 1024 * 1024
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Token {
+    /// 令牌名，客户端以 `<name>:<密钥>` 的形式发送
+    pub name: String,
+    /// 密钥的 argon2 哈希，使用 `deploycli hash-password` 生成
+    pub hash: Option<String>,
+    pub scopes: Vec<Scope>,
+    /// HMAC 签名用的共享密钥，设置后客户端可以签名请求
+    pub signing_key: Option<String>,
+    /// 只接受签名请求，拒绝明文密钥
+    #[serde(default)]
+    pub require_signature: bool,
 }
 
 #[derive(Deserialize, Debug)]
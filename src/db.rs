@@ -1,9 +1,43 @@
+use std::path::Path;
 use std::sync::{Arc, LazyLock};
 
 use anyhow::anyhow;
-use polodb_core::{CollectionT, Database, bson::doc};
+use polodb_core::{CollectionT, Database, bson::{self, Document, doc}};
 
-use crate::utils::Task;
+use deploycli::{AuditFilter, AuditRecord, MANIFEST_FILE, Profile, Task, TaskId, TaskQuery, TaskVersion};
+use deploycli::{content_hash, load_manifest, unix_now};
+use log::{error, info};
+use serde::Serialize;
+
+use crate::store;
+use crate::vault::StoredSecret;
+
+/// 重建任务表或导入任务目录的结果
+#[derive(Debug, Default, Serialize)]
+pub struct UpdateReport {
+    /// 导入的新版本
+    pub imported: Vec<String>,
+    /// 跳过的任务及原因
+    pub skipped: Vec<String>,
+}
+
+impl UpdateReport {
+    fn skip(&mut self, task: &str, reason: String) {
+        error!("Skipping task {}: {}", task, reason);
+        self.skipped.push(format!("{}: {}", task, reason));
+    }
+}
+
+/// 旧版本直接从这里提供任务，现在只作为 `import_tasks` 的来源
+const LEGACY_TASKS_DIR: &str = "./tasks";
+
+/// 查询里用的正则，pattern 里的用户输入要先转义
+fn regex(pattern: String) -> bson::Regex {
+    bson::Regex {
+        pattern,
+        options: String::new(),
+    }
+}
 
 pub struct TaskDatabase {
     db: Arc<Database>,
@@ -17,48 +51,134 @@ impl TaskDatabase {
         TaskDatabase { db }
     }
 
-    /// 检测数据目录并更新修改到数据库的调用函数
-    pub fn update(&self) -> anyhow::Result<()> {
-        // 检查数据目录是否存在
-        if !std::path::Path::new("./tasks").exists() {
-            return Err(anyhow!("Data directory does not exist"));
-        }
+    /// 整理存储并按每个任务的最新版本重建任务表，旧布局的版本目录转成快照。
+    /// 读不出任务的版本会报告出来，数据库里已有的记录保持不变；还在 ./tasks 里没有导入的任务也保留
+    pub fn update(&self) -> anyhow::Result<UpdateReport> {
+        let mut report = UpdateReport::default();
+        store::migrate_version_dirs()?;
         // 先获取所有任务
         let mut tasks = self.get_all_tasks()?;
-        // 检查任务目录是否存在
-        for entry in std::fs::read_dir("./tasks")? {
+        for (task_id, snapshot) in store::latest_versions()? {
+            // 不管能否解析，存储里有的任务都不从数据库删除
+            tasks.retain(|t| t.id().ok().as_ref() != Some(&task_id));
+            // 清单不合法的任务单独跳过，不影响其它任务
+            let task = match store::read_task(&snapshot) {
+                Ok(task) if task.id().ok().as_ref() == Some(&task_id) => task,
+                Ok(_) => {
+                    report.skip(&task_id.dir_name(), "config.toml does not match".to_string());
+                    continue;
+                }
+                Err(e) => {
+                    report.skip(&task_id.dir_name(), e.to_string());
+                    continue;
+                }
+            };
+            self.add_task(&task)?;
+        }
+        // tasks里剩下的就是数据库里但是存储里没有的任务
+        for task in tasks {
+            // 旧布局的任务还在 ./tasks 里、只是没有导入时保留记录，提示先导入
+            let legacy = format!("{}-{}", task.name, task.uuid);
+            if Path::new(LEGACY_TASKS_DIR).join(&legacy).is_dir() {
+                report.skip(&legacy, "not imported yet, run `deploy import` first".to_string());
+                continue;
+            }
+            // 删除数据库里的任务
+            self.delete_raw(&task.uuid, &task.name)?;
+        }
+        Ok(report)
+    }
+
+    /// 把 ./tasks 下的任务目录导入为新版本，内容与最新版本相同时不生成新版本。
+    /// 目录保持不动，由管理员显式调用
+    pub fn import_tasks(&self, actor: &str) -> anyhow::Result<UpdateReport> {
+        let mut report = UpdateReport::default();
+        if !Path::new(LEGACY_TASKS_DIR).exists() {
+            return Err(anyhow!("Data directory does not exist"));
+        }
+        for entry in std::fs::read_dir(LEGACY_TASKS_DIR)? {
             let entry = entry?;
             let path = entry.path();
             if path.is_dir() {
-                let config_path = path.join("config.toml");
+                // 目录名不是合法任务标识的直接跳过
+                let dir_name = entry.file_name().to_string_lossy().to_string();
+                let dir_id = match TaskId::from_dir_name(&dir_name) {
+                    Ok(id) => id,
+                    Err(e) => {
+                        report.skip(&dir_name, e.to_string());
+                        continue;
+                    }
+                };
+                let config_path = path.join(MANIFEST_FILE);
                 if config_path.exists() {
-                    let content = std::fs::read_to_string(&config_path)?;
-                    let task: Task = toml::from_str(&content)?;
-                    self.add_task(&task)?;
-                    // 从tasks中删除已存在的任务
-                    tasks.retain(|t| t.uuid != task.uuid && t.name != task.name);
+                    // 清单不合法的任务单独跳过，不影响其它任务
+                    let task = match load_manifest(&path) {
+                        Ok(task) => task,
+                        Err(e) => {
+                            report.skip(&dir_name, e.to_string());
+                            continue;
+                        }
+                    };
+                    if task.id().ok().as_ref() != Some(&dir_id) {
+                        report.skip(&dir_name, "config.toml does not match".to_string());
+                        continue;
+                    }
+                    let versions = self.get_versions(&dir_id)?;
+                    let hash = content_hash(&path)?;
+                    // 最新版本的快照也要在，否则即使哈希相同也重新导入，删除目录前内容必须已经保存
+                    let stored = store::versions(&dir_id)?;
+                    let up_to_date = versions
+                        .last()
+                        .is_some_and(|v| v.hash == hash && stored.contains(&v.version));
+                    if !up_to_date {
+                        let next = versions.last().map(|v| v.version).unwrap_or(0) + 1;
+                        let (version, _) = store::save_version(&dir_id, next, &path)?;
+                        self.add_version(&TaskVersion {
+                            task: dir_id.dir_name(),
+                            version,
+                            timestamp: unix_now(),
+                            hash,
+                            actor: actor.to_string(),
+                            rollback_of: None,
+                        })?;
+                        self.add_task(&task)?;
+                        info!("Imported {} as version {}", dir_name, version);
+                        report.imported.push(format!("{} as version {}", dir_name, version));
+                    }
                 }
             }
         }
-        // tasks里剩下的就是数据库里但是文件里没有的任务
-        for task in tasks {
-            // 删除数据库里的任务
-            self.delete_tasks(&task.uuid, &task.name)?;
-        }
-        Ok(())
+        Ok(report)
     }
 
     /// 添加任务到数据库
     pub fn add_task(&self, task: &Task) -> anyhow::Result<()> {
+        // 统一用规范化的 uuid 存储，分类存成小写，搜索时才能在库里按分类精确匹配
+        let id = task.id()?;
+        let task = &Task {
+            uuid: id.uuid(),
+            name: id.name().to_string(),
+            category: task.category.as_ref().map(|c| c.to_lowercase()),
+            ..task.clone()
+        };
         let collection = self.db.collection::<Task>("tasks");
         // 检查任务是否已存在
         let existing_task: Option<Task> =
             collection.find_one(doc! { "uuid": &task.uuid, "name": &task.name })?;
         if existing_task.is_some() {
-            // 更新description
+            // 更新config.toml里除标识以外的字段
             collection.update_one(
                 doc! { "uuid": &task.uuid, "name": &task.name },
-                doc! { "$set": { "description": &task.description } },
+                doc! { "$set": {
+                    "description": &task.description,
+                    "params": bson::to_bson(&task.params)?,
+                    "depends_on": bson::to_bson(&task.depends_on)?,
+                    "tags": bson::to_bson(&task.tags)?,
+                    "category": bson::to_bson(&task.category)?,
+                    "platform": bson::to_bson(&task.platform)?,
+                    "actions": bson::to_bson(&task.actions)?,
+                    "modes": bson::to_bson(&task.modes)?,
+                } },
             )?;
             return Ok(());
         }
@@ -66,11 +186,11 @@ impl TaskDatabase {
         Ok(())
     }
 
-    #[allow(unused)]
     /// 根据 UUID 获取任务
-    pub fn get_task(&self, uuid: &str, name: &str) -> anyhow::Result<Task> {
+    pub fn get_task(&self, id: &TaskId) -> anyhow::Result<Task> {
         let collection = self.db.collection::<Task>("tasks");
-        let task: Option<Task> = collection.find_one(doc! { "uuid": uuid, "name": name })?;
+        let task: Option<Task> =
+            collection.find_one(doc! { "uuid": id.uuid(), "name": id.name() })?;
         if task.is_none() {
             return Err(anyhow!("Task not found"));
         }
@@ -87,8 +207,44 @@ impl TaskDatabase {
         Ok(tasks)
     }
 
+    /// 按引用找出可能匹配的任务：完整 uuid、任务名或 uuid 前缀，再交给 `find_task` 决定是哪一个，
+    /// 不用每次把所有任务读出来
+    pub fn find_task_candidates(&self, reference: &str) -> anyhow::Result<Vec<Task>> {
+        let uuid = reference.to_ascii_lowercase();
+        let mut any = vec![doc! { "uuid": &uuid }, doc! { "name": reference }];
+        if !uuid.is_empty() {
+            any.push(doc! { "uuid": { "$regex": regex(format!("^{}", regex::escape(&uuid))) } });
+        }
+        let collection = self.db.collection::<Task>("tasks");
+        let tasks = collection
+            .find(doc! { "$or": any })
+            .run()?
+            .collect::<polodb_core::Result<Vec<Task>>>()?;
+        Ok(tasks)
+    }
+
+    /// 按条件搜索任务，分类在数据库里过滤，其余条件在内存里处理。
+    /// 分类入库时已转成小写，查询条件同样转成小写，与 `TaskQuery::matches` 一样不区分大小写
+    pub fn search_tasks(&self, query: &TaskQuery) -> anyhow::Result<Vec<Task>> {
+        let mut filter = Document::new();
+        if let Some(category) = &query.category {
+            filter.insert("category", category.to_lowercase());
+        }
+        let collection = self.db.collection::<Task>("tasks");
+        let tasks = collection
+            .find(filter)
+            .run()?
+            .collect::<polodb_core::Result<Vec<Task>>>()?;
+        Ok(query.apply(tasks))
+    }
+
     /// 删除任务
-    pub fn delete_tasks(&self, uuid: &str, name: &str) -> anyhow::Result<()> {
+    pub fn delete_task(&self, id: &TaskId) -> anyhow::Result<()> {
+        self.delete_raw(&id.uuid(), id.name())
+    }
+
+    /// 按原始字段删除，用于清理库里可能存在的不合法旧记录
+    fn delete_raw(&self, uuid: &str, name: &str) -> anyhow::Result<()> {
         let collection = self.db.collection::<Task>("tasks");
         let result = collection.delete_one(doc! { "uuid": uuid, "name": name })?;
         if result.deleted_count == 0 {
@@ -96,6 +252,141 @@ impl TaskDatabase {
         }
         Ok(())
     }
+
+    /// 记录任务的一个新版本
+    pub fn add_version(&self, version: &TaskVersion) -> anyhow::Result<()> {
+        let collection = self.db.collection::<TaskVersion>("versions");
+        collection.insert_one(version)?;
+        Ok(())
+    }
+
+    /// 获取任务的所有版本，按版本号升序
+    pub fn get_versions(&self, id: &TaskId) -> anyhow::Result<Vec<TaskVersion>> {
+        let collection = self.db.collection::<TaskVersion>("versions");
+        let mut versions = collection
+            .find(doc! { "task": id.dir_name() })
+            .run()?
+            .collect::<polodb_core::Result<Vec<TaskVersion>>>()?;
+        versions.sort_by_key(|v| v.version);
+        Ok(versions)
+    }
+
+    /// 删除任务的所有版本记录
+    pub fn delete_versions(&self, id: &TaskId) -> anyhow::Result<()> {
+        let collection = self.db.collection::<TaskVersion>("versions");
+        collection.delete_many(doc! { "task": id.dir_name() })?;
+        Ok(())
+    }
+
+    /// 保存任务密钥，同名的会被覆盖
+    pub fn set_secret(&self, secret: &StoredSecret) -> anyhow::Result<()> {
+        let collection = self.db.collection::<StoredSecret>("secrets");
+        collection.delete_one(doc! { "task": &secret.task, "name": &secret.name })?;
+        collection.insert_one(secret)?;
+        Ok(())
+    }
+
+    /// 获取任务的所有密钥（密文）
+    pub fn get_secrets(&self, id: &TaskId) -> anyhow::Result<Vec<StoredSecret>> {
+        let collection = self.db.collection::<StoredSecret>("secrets");
+        let mut secrets = collection
+            .find(doc! { "task": id.dir_name() })
+            .run()?
+            .collect::<polodb_core::Result<Vec<StoredSecret>>>()?;
+        secrets.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(secrets)
+    }
+
+    /// 删除任务的一个密钥
+    pub fn delete_secret(&self, id: &TaskId, name: &str) -> anyhow::Result<()> {
+        let collection = self.db.collection::<StoredSecret>("secrets");
+        let result = collection.delete_one(doc! { "task": id.dir_name(), "name": name })?;
+        if result.deleted_count == 0 {
+            return Err(anyhow!("Secret not found"));
+        }
+        Ok(())
+    }
+
+    /// 删除任务的所有密钥
+    pub fn delete_secrets(&self, id: &TaskId) -> anyhow::Result<()> {
+        let collection = self.db.collection::<StoredSecret>("secrets");
+        collection.delete_many(doc! { "task": id.dir_name() })?;
+        Ok(())
+    }
+
+    /// 保存 profile，同名的会被替换
+    pub fn set_profile(&self, profile: &Profile) -> anyhow::Result<()> {
+        let collection = self.db.collection::<Profile>("profiles");
+        collection.delete_one(doc! { "name": &profile.name })?;
+        collection.insert_one(profile)?;
+        Ok(())
+    }
+
+    /// 获取所有 profile，按名字排序
+    pub fn get_profiles(&self) -> anyhow::Result<Vec<Profile>> {
+        let collection = self.db.collection::<Profile>("profiles");
+        let mut profiles = collection
+            .find(doc! {})
+            .run()?
+            .collect::<polodb_core::Result<Vec<Profile>>>()?;
+        profiles.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(profiles)
+    }
+
+    pub fn get_profile(&self, name: &str) -> anyhow::Result<Option<Profile>> {
+        let collection = self.db.collection::<Profile>("profiles");
+        Ok(collection.find_one(doc! { "name": name })?)
+    }
+
+    /// 删除 profile，返回是否存在
+    pub fn delete_profile(&self, name: &str) -> anyhow::Result<bool> {
+        let collection = self.db.collection::<Profile>("profiles");
+        let result = collection.delete_one(doc! { "name": name })?;
+        Ok(result.deleted_count > 0)
+    }
+
+    /// 记录一条审计日志
+    pub fn add_audit(&self, record: &AuditRecord) -> anyhow::Result<()> {
+        let collection = self.db.collection::<AuditRecord>("audit");
+        collection.insert_one(record)?;
+        Ok(())
+    }
+
+    /// 按条件查询审计日志，按时间倒序
+    pub fn get_audit(&self, filter: &AuditFilter) -> anyhow::Result<Vec<AuditRecord>> {
+        let mut query = Document::new();
+        if let Some(actor) = &filter.actor {
+            query.insert("actor", actor);
+        }
+        if let Some(action) = &filter.action {
+            query.insert("action", action);
+        }
+        let mut range = Document::new();
+        if let Some(since) = filter.since {
+            range.insert("$gte", since as i64);
+        }
+        if let Some(until) = filter.until {
+            range.insert("$lt", until as i64);
+        }
+        if !range.is_empty() {
+            query.insert("timestamp", range);
+        }
+        // 任务既可以按完整标识查，也可以按名字查，按名字时匹配该名字下所有 uuid
+        if let Some(task) = &filter.task {
+            match TaskId::from_dir_name(task) {
+                Ok(id) => query.insert("task", id.dir_name()),
+                Err(_) => query.insert("task", doc! { "$regex": regex(format!("^{}-[0-9a-f-]{{36}}$", regex::escape(task))) }),
+            };
+        }
+        // 过滤、排序和条数限制都交给数据库，不把整个日志读进内存
+        let collection = self.db.collection::<AuditRecord>("audit");
+        let mut find = collection.find(query).sort(doc! { "timestamp": -1 });
+        if let Some(limit) = filter.limit {
+            find = find.limit(limit);
+        }
+        let records = find.run()?.collect::<polodb_core::Result<Vec<AuditRecord>>>()?;
+        Ok(records)
+    }
 }
 
 /// 全局的 TaskDatabase 实例
@@ -0,0 +1,134 @@
+use std::collections::HashSet;
+use thiserror::Error;
+
+use crate::Task;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum DependencyError {
+    #[error("task `{0}` depends on unknown task `{1}`")]
+    Missing(String, String),
+    #[error("task `{0}` depends on `{1}`, which matches more than one task, use its uuid")]
+    Ambiguous(String, String),
+    #[error("dependency cycle: {0}")]
+    Cycle(String),
+}
+
+fn key(task: &Task) -> String {
+    format!("{}-{}", task.name, task.uuid.to_ascii_lowercase())
+}
+
+/// 按 uuid 或任务名查找 depends_on 里引用的任务，名字重复时要求使用 uuid
+fn find_dependency<'a>(tasks: &'a [Task], from: &Task, reference: &str) -> Result<&'a Task, DependencyError> {
+    if let Some(task) = tasks.iter().find(|t| t.uuid.eq_ignore_ascii_case(reference)) {
+        return Ok(task);
+    }
+    let mut matches = tasks.iter().filter(|t| t.name == reference);
+    match (matches.next(), matches.next()) {
+        (Some(task), None) => Ok(task),
+        (Some(_), Some(_)) => Err(DependencyError::Ambiguous(from.name.clone(), reference.to_string())),
+        (None, _) => Err(DependencyError::Missing(from.name.clone(), reference.to_string())),
+    }
+}
+
+/// 深度优先遍历，依赖先于依赖它的任务加入 order
+fn visit(
+    tasks: &[Task],
+    task: &Task,
+    path: &mut Vec<String>,
+    done: &mut HashSet<String>,
+    order: &mut Vec<Task>,
+) -> Result<(), DependencyError> {
+    let key = key(task);
+    if done.contains(&key) {
+        return Ok(());
+    }
+    if let Some(start) = path.iter().position(|k| *k == key) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(key);
+        return Err(DependencyError::Cycle(cycle.join(" -> ")));
+    }
+    path.push(key.clone());
+    for reference in &task.depends_on {
+        let dependency = find_dependency(tasks, task, reference)?;
+        visit(tasks, dependency, path, done, order)?;
+    }
+    path.pop();
+    done.insert(key);
+    order.push(task.clone());
+    Ok(())
+}
+
+/// 计算运行 root 需要的所有任务，按拓扑顺序排列，root 在最后
+pub fn dependency_order(tasks: &[Task], root: &Task) -> Result<Vec<Task>, DependencyError> {
+    let mut order = Vec::new();
+    visit(tasks, root, &mut Vec::new(), &mut HashSet::new(), &mut order)?;
+    Ok(order)
+}
+
+/// 检查所有任务的依赖都存在且没有环
+pub fn check_dependencies(tasks: &[Task]) -> Result<(), DependencyError> {
+    let mut done = HashSet::new();
+    for task in tasks {
+        visit(tasks, task, &mut Vec::new(), &mut done, &mut Vec::new())?;
+    }
+    Ok(())
+}
+
+/// 直接依赖 target 的任务
+pub fn dependents<'a>(tasks: &'a [Task], target: &Task) -> Vec<&'a Task> {
+    tasks
+        .iter()
+        .filter(|t| {
+            t.depends_on.iter().any(|reference| {
+                find_dependency(tasks, t, reference).is_ok_and(|d| key(d) == key(target))
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(name: &str, uuid: &str, depends_on: &[&str]) -> Task {
+        Task {
+            uuid: uuid.to_string(),
+            name: name.to_string(),
+            description: String::new(),
+            params: Vec::new(),
+            depends_on: depends_on.iter().map(|s| s.to_string()).collect(),
+            tags: Vec::new(),
+            category: None,
+            platform: None,
+            actions: Default::default(),
+            modes: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_dependency_order() {
+        let tasks = vec![
+            task("app", "u3", &["docker", "u1"]),
+            task("docker", "u2", &["base"]),
+            task("base", "u1", &[]),
+        ];
+        let order = dependency_order(&tasks, &tasks[0]).unwrap();
+        let names: Vec<_> = order.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["base", "docker", "app"]);
+        assert_eq!(check_dependencies(&tasks), Ok(()));
+        assert_eq!(dependents(&tasks, &tasks[2]).len(), 2);
+    }
+
+    #[test]
+    fn test_dependency_errors() {
+        let tasks = vec![task("a", "u1", &["b"]), task("b", "u2", &["a"])];
+        assert_eq!(
+            check_dependencies(&tasks),
+            Err(DependencyError::Cycle("a-u1 -> b-u2 -> a-u1".to_string()))
+        );
+        let tasks = vec![task("a", "u1", &["missing"])];
+        assert!(matches!(check_dependencies(&tasks), Err(DependencyError::Missing(..))));
+        let tasks = vec![task("a", "u1", &["b"]), task("b", "u2", &[]), task("b", "u3", &[])];
+        assert!(matches!(check_dependencies(&tasks), Err(DependencyError::Ambiguous(..))));
+    }
+}
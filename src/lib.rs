@@ -1,3 +1,29 @@
+mod actions;
+mod audit;
+mod blobs;
+mod deps;
+mod manifest;
+mod package;
+mod params;
+mod platform;
+mod profile;
+mod search;
+mod secrets;
+mod signature;
+mod tls;
 mod utils;
 
-pub use utils::*;
\ No newline at end of file
+pub use actions::*;
+pub use audit::*;
+pub use blobs::*;
+pub use deps::*;
+pub use manifest::*;
+pub use package::*;
+pub use params::*;
+pub use platform::*;
+pub use profile::*;
+pub use search::*;
+pub use secrets::*;
+pub use signature::*;
+pub use tls::*;
+pub use utils::*;
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use log::warn;
+use salvo::http::header::RETRY_AFTER;
+use salvo::prelude::*;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::config::CFG;
+
+/// 认证失败计数和锁定时长的配置
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Lockout {
+    /// 连续失败多少次后开始锁定
+    pub max_failures: u32,
+    /// 第一次锁定的时长（秒），之后每多失败一次翻倍
+    pub base_secs: u64,
+    /// 锁定时长的上限（秒）
+    pub max_secs: u64,
+    /// 超过这么久（秒）没有新的失败就清零计数
+    pub reset_secs: u64,
+}
+
+impl Default for Lockout {
+    fn default() -> Self {
+        Lockout {
+            max_failures: 5,
+            base_secs: 30,
+            max_secs: 3600,
+            reset_secs: 900,
+        }
+    }
+}
+
+/// 某条路由的限速：每 `per` 秒最多 `requests` 个请求
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct Rate {
+    pub requests: u32,
+    pub per: u64,
+}
+
+struct Failure {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// 按 IP 和令牌名记录认证失败，连续失败后按指数退避锁定
+pub struct FailureTracker {
+    config: Lockout,
+    failures: Mutex<HashMap<String, Failure>>,
+}
+
+impl FailureTracker {
+    pub fn new(config: Lockout) -> Self {
+        FailureTracker {
+            config,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 仍处于锁定中时返回剩余时长
+    pub fn locked(&self, key: &str, now: Instant) -> Option<Duration> {
+        let failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        let locked_until = failures.get(key)?.locked_until?;
+        locked_until.checked_duration_since(now).filter(|d| !d.is_zero())
+    }
+
+    /// 记录一次失败，达到阈值时返回本次锁定的时长
+    pub fn fail(&self, key: &str, now: Instant) -> Option<Duration> {
+        let reset = Duration::from_secs(self.config.reset_secs);
+        let mut failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        // 顺带清理过期的记录，避免随机 IP 把表撑大
+        failures.retain(|_, f| {
+            now.duration_since(f.last) < reset || f.locked_until.is_some_and(|t| t > now)
+        });
+        let failure = failures.entry(key.to_string()).or_insert(Failure {
+            count: 0,
+            last: now,
+            locked_until: None,
+        });
+        failure.count += 1;
+        failure.last = now;
+        if failure.count < self.config.max_failures {
+            return None;
+        }
+        let exponent = (failure.count - self.config.max_failures).min(32);
+        let secs = self
+            .config
+            .base_secs
+            .saturating_mul(1 << exponent)
+            .min(self.config.max_secs);
+        let duration = Duration::from_secs(secs);
+        failure.locked_until = Some(now + duration);
+        Some(duration)
+    }
+
+    /// 认证成功后清零
+    pub fn succeed(&self, key: &str) {
+        self.failures
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(key);
+    }
+}
+
+pub static FAILURES: LazyLock<FailureTracker> =
+    LazyLock::new(|| FailureTracker::new(CFG.limits.lockout.clone()));
+
+/// 固定窗口计数的限速器
+pub struct RateLimiter {
+    rate: Rate,
+    windows: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: Rate) -> Self {
+        RateLimiter {
+            rate,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 计入一个请求，超出限制时返回需要等待的时长
+    pub fn check(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let per = Duration::from_secs(self.rate.per);
+        let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+        windows.retain(|_, (start, _)| now.duration_since(*start) < per);
+        let (start, count) = windows.entry(key.to_string()).or_insert((now, 0));
+        if *count >= self.rate.requests {
+            return Err(per - now.duration_since(*start));
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+/// 路由级别的限速，按客户端 IP 计数
+pub struct RateLimit(RateLimiter);
+
+impl RateLimit {
+    pub fn new(rate: Rate) -> Self {
+        RateLimit(RateLimiter::new(rate))
+    }
+}
+
+#[handler]
+impl RateLimit {
+    async fn handle(&self, req: &mut Request, res: &mut Response) {
+        if let Err(wait) = self.0.check(&client_ip(req), Instant::now()) {
+            warn!("Rate limit exceeded by {} on {}", client_ip(req), req.uri().path());
+            too_many_requests(res, wait);
+        }
+    }
+}
+
+/// 客户端 IP，不含端口
+pub fn client_ip(req: &Request) -> String {
+    match req.remote_addr().clone().into_std() {
+        Some(addr) => addr.ip().to_string(),
+        None => req.remote_addr().to_string(),
+    }
+}
+
+/// 返回 429，并在 Retry-After 中给出需要等待的秒数
+pub fn too_many_requests(res: &mut Response, wait: Duration) {
+    // 向上取整，避免客户端按 0 秒立即重试
+    let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    res.add_header(RETRY_AFTER, secs.to_string(), true).ok();
+    res.stuff(StatusCode::TOO_MANY_REQUESTS, Json(json!("Too Many Requests")));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exponential_lockout() {
+        let tracker = FailureTracker::new(Lockout {
+            max_failures: 3,
+            base_secs: 10,
+            max_secs: 30,
+            reset_secs: 900,
+        });
+        let now = Instant::now();
+        assert_eq!(tracker.fail("ip:1.2.3.4", now), None);
+        assert_eq!(tracker.fail("ip:1.2.3.4", now), None);
+        assert!(tracker.locked("ip:1.2.3.4", now).is_none());
+        assert_eq!(tracker.fail("ip:1.2.3.4", now), Some(Duration::from_secs(10)));
+        assert_eq!(tracker.fail("ip:1.2.3.4", now), Some(Duration::from_secs(20)));
+        assert_eq!(tracker.fail("ip:1.2.3.4", now), Some(Duration::from_secs(30)));
+        assert!(tracker.locked("ip:1.2.3.4", now).is_some());
+        assert!(tracker.locked("ip:1.2.3.4", now + Duration::from_secs(31)).is_none());
+        assert!(tracker.locked("ip:5.6.7.8", now).is_none());
+        tracker.succeed("ip:1.2.3.4");
+        assert!(tracker.locked("ip:1.2.3.4", now).is_none());
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(Rate { requests: 2, per: 60 });
+        let now = Instant::now();
+        assert!(limiter.check("a", now).is_ok());
+        assert!(limiter.check("a", now).is_ok());
+        assert_eq!(limiter.check("a", now), Err(Duration::from_secs(60)));
+        assert!(limiter.check("b", now).is_ok());
+        assert!(limiter.check("a", now + Duration::from_secs(60)).is_ok());
+    }
+}
@@ -1,16 +1,72 @@
+use clap::{Parser, Subcommand};
+use std::io::IsTerminal;
 use config::CFG;
+use deploycli::cert_fingerprint;
 use log::info;
-use router::create_router;
+use mtls::MtlsAcceptor;
+use router::{check_rate_limits, create_router};
+use rustls::pki_types::CertificateDer;
+use rustls::pki_types::pem::PemObject;
+use salvo::conn::rustls::{Keycert, RustlsConfig};
 use salvo::prelude::*;
 
+mod auth;
 mod config;
 mod result;
 mod router;
-mod utils;
 mod db;
+mod mtls;
+mod limiter;
+mod vault;
+mod store;
+
+/// Deploy server
+#[derive(Parser)]
+#[command(name = "deploycli", version, about = "Deploy server")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Hash a password for the `hash` field of a `[[server.tokens]]` entry in config.toml
+    HashPassword {
+        /// Password to hash, read from stdin if omitted
+        password: Option<String>,
+    },
+}
+
+/// 从 stdin 读取要哈希的密码，终端上输入时不回显
+fn read_password() -> anyhow::Result<String> {
+    if std::io::stdin().is_terminal() {
+        return Ok(rpassword::prompt_password("Password: ")?);
+    }
+    let mut input = String::new();
+    if std::io::stdin().read_line(&mut input)? == 0 {
+        return Err(anyhow::anyhow!("no password given on stdin"));
+    }
+    Ok(input.trim_end_matches(['\r', '\n']).to_string())
+}
 
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+    if let Some(Commands::HashPassword { password }) = cli.command {
+        let hashed = match password {
+            Some(p) => Ok(p),
+            None => read_password(),
+        }
+        .and_then(|password| auth::hash_password(&password));
+        match hashed {
+            Ok(hash) => println!("{}", hash),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
     let _guard = clia_tracing_config::build()
         .filter_level(&CFG.log.filter_level)
         .with_ansi(CFG.log.with_ansi)
@@ -24,18 +80,58 @@ async fn main() {
         .rolling(&CFG.log.rolling)
         .init();
     info!("Starting server");
-    let acceptor = TcpListener::new(&CFG.server.address).bind().await;
-    let server = Server::new(acceptor);
-    #[allow(unused_variables)] // 防止开发时候报WARN
-    let handle = server.handle();
+    // 启动时就加载密钥，主密钥有问题时尽早失败
+    std::sync::LazyLock::force(&vault::VAULT);
     // 初始化路由
     let router = create_router();
+    // 限速配置里写错的路由在启动时报出来
+    if let Err(e) = check_rate_limits() {
+        panic!("Invalid config: {}", e);
+    }
     // 优雅关机
-    // 生产环境再启用
+    // 生产环境再启用，需在下面两个分支里分别取 server.handle()
     // #[cfg(not(debug_assertions))] // 这个代表非debug模式
     // tokio::spawn(async move {
     //     shutdown_signal().await;
     //     handle.stop_graceful(None);
     // });
-    server.serve(router).await;
+    let listener = TcpListener::new(&CFG.server.address);
+    match &CFG.server.tls {
+        Some(tls) => {
+            let keycert = Keycert::new()
+                .cert_from_path(&tls.cert)
+                .expect("Failed to read TLS certificate")
+                .key_from_path(&tls.key)
+                .expect("Failed to read TLS private key");
+            if let Some(cert) = CertificateDer::pem_file_iter(&tls.cert)
+                .ok()
+                .and_then(|mut certs| certs.next())
+                .and_then(|cert| cert.ok())
+            {
+                // 方便客户端配置证书指纹
+                info!("TLS certificate fingerprint: {}", cert_fingerprint(&cert));
+            }
+            let rustls_config = RustlsConfig::new(keycert);
+            match &tls.client_ca {
+                Some(client_ca) => {
+                    let rustls_config = rustls_config
+                        .client_auth_required_path(client_ca)
+                        .expect("Failed to read client CA certificate");
+                    let acceptor = MtlsAcceptor::new(listener.bind().await, rustls_config)
+                        .expect("Failed to build TLS config");
+                    info!("Serving HTTPS with client certificates on {}", CFG.server.address);
+                    Server::new(acceptor).serve(router).await;
+                }
+                None => {
+                    let acceptor = listener.rustls(rustls_config).bind().await;
+                    info!("Serving HTTPS on {}", CFG.server.address);
+                    Server::new(acceptor).serve(router).await;
+                }
+            }
+        }
+        None => {
+            let acceptor = listener.bind().await;
+            Server::new(acceptor).serve(router).await;
+        }
+    }
 }
@@ -0,0 +1,275 @@
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use thiserror::Error;
+
+use crate::{PackageEntry, Param, Platform, Task, TaskIdError, check_actions, check_params, is_plain_relative, package_entries, parse_mode};
+
+/// 支持的最高清单版本，config.toml 里不写 `schema` 时按 1 处理
+pub const MANIFEST_SCHEMA: u32 = 1;
+
+/// 任务目录里的清单文件名
+pub const MANIFEST_FILE: &str = "config.toml";
+
+/// config.toml 的严格格式，不认识的键直接报错
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    schema: Option<u32>,
+    uuid: String,
+    name: String,
+    description: String,
+    #[serde(default)]
+    params: Vec<Param>,
+    #[serde(default)]
+    depends_on: Vec<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    category: Option<String>,
+    platform: Option<Platform>,
+    #[serde(default)]
+    actions: BTreeMap<String, String>,
+    #[serde(default)]
+    modes: BTreeMap<String, String>,
+}
+
+impl From<Manifest> for Task {
+    fn from(manifest: Manifest) -> Self {
+        Task {
+            uuid: manifest.uuid,
+            name: manifest.name,
+            description: manifest.description,
+            params: manifest.params,
+            depends_on: manifest.depends_on,
+            tags: manifest.tags,
+            category: manifest.category,
+            platform: manifest.platform,
+            actions: manifest.actions,
+            modes: manifest.modes,
+        }
+    }
+}
+
+/// 清单里的一个问题，能定位时带上行号和列号（从 1 开始）
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "{}:{}:{}: {}", MANIFEST_FILE, line, column, self.message),
+            _ => write!(f, "{}: {}", MANIFEST_FILE, self.message),
+        }
+    }
+}
+
+/// 清单校验失败，包含找到的所有问题
+#[derive(Error, Debug)]
+pub struct ManifestError(pub Vec<Diagnostic>);
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines: Vec<String> = self.0.iter().map(|d| d.to_string()).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl ManifestError {
+    fn single(message: String) -> Self {
+        ManifestError(vec![Diagnostic {
+            line: None,
+            column: None,
+            message,
+        }])
+    }
+}
+
+/// 字节偏移对应的行号和列号
+fn line_column(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+    (line, column)
+}
+
+/// 查找以 needle 开头的第一行，用来定位语义错误
+fn locate(content: &str, needle: &str) -> (Option<usize>, Option<usize>) {
+    for (i, line) in content.lines().enumerate() {
+        let trimmed = line.trim_start();
+        let rest = trimmed.strip_prefix(needle);
+        // 键名后面必须是空格或 `=`，避免 name 匹配到 name_x
+        if rest.is_some_and(|r| needle.starts_with('[') || r.trim_start().starts_with('=')) {
+            return (Some(i + 1), Some(line.len() - trimmed.len() + 1));
+        }
+    }
+    (None, None)
+}
+
+/// 解析并校验清单内容：键名、版本、任务名和 uuid 的格式、参数和动作的声明
+pub fn parse_manifest(content: &str) -> Result<Task, ManifestError> {
+    let manifest: Manifest = toml::from_str(content).map_err(|e| {
+        let (line, column) = match e.span() {
+            Some(span) => {
+                let (line, column) = line_column(content, span.start);
+                (Some(line), Some(column))
+            }
+            None => (None, None),
+        };
+        ManifestError(vec![Diagnostic {
+            line,
+            column,
+            message: e.message().to_string(),
+        }])
+    })?;
+    let mut diagnostics = Vec::new();
+    let mut report = |needle: &str, message: String| {
+        let (line, column) = locate(content, needle);
+        diagnostics.push(Diagnostic { line, column, message });
+    };
+    if let Some(schema) = manifest.schema
+        && !(1..=MANIFEST_SCHEMA).contains(&schema)
+    {
+        report(
+            "schema",
+            format!("unsupported schema {}, this version supports 1 to {}", schema, MANIFEST_SCHEMA),
+        );
+    }
+    let task = Task::from(manifest);
+    match task.id() {
+        Err(e @ TaskIdError::InvalidUuid(_)) => report("uuid", e.to_string()),
+        Err(e) => report("name", e.to_string()),
+        Ok(_) => {}
+    }
+    if let Err(e) = check_params(&task.params) {
+        report("[[params]]", e.to_string());
+    }
+    if let Err(e) = check_actions(&task.actions) {
+        report("[actions]", e.to_string());
+    }
+    if let Some(reference) = task.depends_on.iter().find(|r| r.trim().is_empty()) {
+        report("depends_on", format!("invalid dependency `{}`", reference));
+    }
+    for (path, mode) in &task.modes {
+        if !is_plain_relative(path) {
+            report("[modes]", format!("invalid path `{}` in modes, use a relative path inside the task", path));
+        } else if parse_mode(mode).is_none() {
+            report("[modes]", format!("invalid mode `{}` for {}, expected an octal mode like \"0644\"", mode, path));
+        }
+    }
+    if diagnostics.is_empty() {
+        Ok(task)
+    } else {
+        Err(ManifestError(diagnostics))
+    }
+}
+
+/// 读取并校验任务目录里的清单，同时检查声明的脚本和权限路径都在任务包里。
+/// 按 `package_entries` 检查而不是看磁盘，被 .deployignore 排除的文件不算存在
+pub fn load_manifest(dir: &Path) -> Result<Task, ManifestError> {
+    let content = fs::read_to_string(dir.join(MANIFEST_FILE))
+        .map_err(|e| ManifestError::single(format!("cannot read {}: {}", MANIFEST_FILE, e)))?;
+    let task = parse_manifest(&content)?;
+    let entries: HashMap<String, PackageEntry> = package_entries(dir)
+        .map_err(|e| ManifestError::single(format!("cannot list the package entries: {}", e)))?
+        .into_iter()
+        .collect();
+    // 脚本可以是文件，也可以是指向包内文件的符号链接
+    let is_script = |script: &str| match entries.get(script) {
+        Some(PackageEntry::File(_)) => true,
+        Some(PackageEntry::Symlink(_)) => dir.join(script).is_file(),
+        _ => false,
+    };
+    let mut diagnostics = Vec::new();
+    let platform = task.platform.clone().unwrap_or_default();
+    for script in platform.required_scripts() {
+        if !is_script(script) {
+            diagnostics.push(Diagnostic {
+                line: None,
+                column: None,
+                message: format!("{} not found in the package", script),
+            });
+        }
+    }
+    for (action, script) in &task.actions {
+        if !is_script(script) {
+            let (line, column) = locate(&content, action);
+            diagnostics.push(Diagnostic {
+                line,
+                column,
+                message: format!("script {} of action {} not found in the package", script, action),
+            });
+        }
+    }
+    for path in task.modes.keys() {
+        if !entries.contains_key(path) {
+            diagnostics.push(Diagnostic {
+                line: None,
+                column: None,
+                message: format!("{} in modes not found in the package", path),
+            });
+        }
+    }
+    if diagnostics.is_empty() {
+        Ok(task)
+    } else {
+        Err(ManifestError(diagnostics))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: &str = "6f1c4a52-9b1e-4f6d-8c1a-2b3c4d5e6f70";
+
+    #[test]
+    fn test_parse_manifest() {
+        let content = format!("schema = 1\nuuid = \"{}\"\nname = \"nginx\"\ndescription = \"\"\n", UUID);
+        assert_eq!(parse_manifest(&content).unwrap().name, "nginx");
+        let typo = format!("{}\n[platfrom]\nos = \"linux\"\n", content);
+        let err = parse_manifest(&typo).unwrap_err();
+        assert_eq!(err.0[0].line, Some(6));
+        assert!(err.0[0].message.contains("platfrom"));
+        let nested = format!("{}\n[[params]]\nname = \"port\"\ntpye = \"int\"\n", content);
+        assert_eq!(parse_manifest(&nested).unwrap_err().0[0].line, Some(8));
+        let invalid = "schema = 2\nuuid = \"nope\"\nname = \"../etc\"\ndescription = \"\"\n";
+        let err = parse_manifest(invalid).unwrap_err();
+        let lines: Vec<_> = err.0.iter().map(|d| d.line).collect();
+        assert_eq!(lines, [Some(1), Some(3)]);
+        let err = parse_manifest(&invalid.replace("../etc", "etc")).unwrap_err();
+        assert_eq!(err.0[1].line, Some(2));
+        let modes = format!("{}[modes]\n\"files/id_rsa\" = \"0600\"\n", content);
+        assert_eq!(parse_manifest(&modes).unwrap().modes["files/id_rsa"], "0600");
+        let err = parse_manifest(&modes.replace("0600", "0800")).unwrap_err();
+        assert_eq!(err.0[0].line, Some(5));
+        assert!(parse_manifest(&modes.replace("files/id_rsa", "../id_rsa")).is_err());
+    }
+
+    #[test]
+    fn test_load_manifest_ignored_paths() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("files")).unwrap();
+        let content = format!(
+            "uuid = \"{}\"\nname = \"nginx\"\ndescription = \"\"\n[actions]\nstop = \"stop.sh\"\n[modes]\n\"files/id_rsa\" = \"0600\"\n",
+            UUID
+        );
+        fs::write(dir.join(MANIFEST_FILE), content).unwrap();
+        fs::write(dir.join("run.sh"), "echo hi").unwrap();
+        fs::write(dir.join("stop.sh"), "echo bye").unwrap();
+        fs::write(dir.join("files/id_rsa"), "key").unwrap();
+        assert!(load_manifest(dir).is_ok());
+        // 文件还在磁盘上，但被 .deployignore 排除后不会进包
+        fs::write(dir.join(crate::IGNORE_FILE), "stop.sh\nfiles/id_rsa\n").unwrap();
+        let err = load_manifest(dir).unwrap_err();
+        assert_eq!(err.0.len(), 2);
+        assert!(err.0[0].message.contains("stop.sh"));
+        assert!(err.0[1].message.contains("files/id_rsa"));
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::task::{Context, Poll, ready};
+
+use log::debug;
+use salvo::conn::rustls::RustlsConfig;
+use salvo::conn::tcp::TcpAcceptor;
+use salvo::conn::{Accepted, Acceptor, Holding, StraightStream};
+use salvo::fuse::FuseFactory;
+use salvo::http::HttpConnection;
+use salvo::http::uri::Scheme;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::{Accept, TlsAcceptor};
+use x509_parser::parse_x509_certificate;
+
+type InnerConn = <TcpAcceptor as Acceptor>::Conn;
+
+/// 一个连接登记的客户端证书
+struct ClientCert {
+    /// 登记它的连接编号，同一个对端地址被新连接复用后，旧连接不能再改动这一项
+    conn_id: u64,
+    /// 证书 CN，握手完成前或证书没有 CN 时为 None
+    subject: Option<String>,
+}
+
+/// 每个 TLS 连接的对端地址与其客户端证书的对应关系。
+/// 接受连接时就登记，覆盖同一地址上已经关闭的连接留下的记录，连接关闭时移除自己登记的那一项
+static CLIENT_CERTS: LazyLock<Mutex<HashMap<String, ClientCert>>> = LazyLock::new(Default::default);
+
+static NEXT_CONN_ID: AtomicU64 = AtomicU64::new(0);
+
+/// 获取某个连接上客户端证书的 CN
+pub fn client_cert_subject(remote_addr: &str) -> Option<String> {
+    CLIENT_CERTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(remote_addr)
+        .and_then(|cert| cert.subject.clone())
+}
+
+/// 新连接登记到对端地址上，同一地址之前的记录一律作废
+fn register(remote_addr: &str) -> u64 {
+    let conn_id = NEXT_CONN_ID.fetch_add(1, Ordering::Relaxed);
+    CLIENT_CERTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(remote_addr.to_string(), ClientCert { conn_id, subject: None });
+    conn_id
+}
+
+/// 握手完成后写入证书 CN，没有 CN 时也写入 None，只改动本连接登记的那一项
+fn set_subject(remote_addr: &str, conn_id: u64, subject: Option<String>) {
+    let mut certs = CLIENT_CERTS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(cert) = certs.get_mut(remote_addr)
+        && cert.conn_id == conn_id
+    {
+        cert.subject = subject;
+    }
+}
+
+/// 连接关闭时移除本连接登记的那一项，地址已经被新连接占用时不动
+fn unregister(remote_addr: &str, conn_id: u64) {
+    let mut certs = CLIENT_CERTS.lock().unwrap_or_else(|e| e.into_inner());
+    if certs.get(remote_addr).is_some_and(|cert| cert.conn_id == conn_id) {
+        certs.remove(remote_addr);
+    }
+}
+
+/// 从 DER 证书中取出 subject 的 CN
+fn common_name(der: &[u8]) -> Option<String> {
+    let (_, cert) = parse_x509_certificate(der).ok()?;
+    let cn = cert.subject().iter_common_name().next()?;
+    cn.as_str().ok().map(|s| s.to_string())
+}
+
+/// 要求客户端证书的 TLS acceptor。salvo 自带的 rustls acceptor 不暴露对端证书，
+/// 这里握手完成后把证书 CN 记录下来，供 auth_middleware 按对端地址查询
+pub struct MtlsAcceptor {
+    inner: TcpAcceptor,
+    tls_acceptor: TlsAcceptor,
+    holdings: Vec<Holding>,
+}
+
+impl MtlsAcceptor {
+    pub fn new(inner: TcpAcceptor, config: RustlsConfig) -> IoResult<Self> {
+        let config = config.try_into().map_err(IoError::other)?;
+        let holdings = inner
+            .holdings()
+            .iter()
+            .map(|h| {
+                let mut h = h.clone();
+                h.http_scheme = Scheme::HTTPS;
+                h
+            })
+            .collect();
+        Ok(MtlsAcceptor {
+            inner,
+            tls_acceptor: TlsAcceptor::from(Arc::new(config)),
+            holdings,
+        })
+    }
+}
+
+impl Acceptor for MtlsAcceptor {
+    type Conn = StraightStream<MtlsStream>;
+
+    fn holdings(&self) -> &[Holding] {
+        &self.holdings
+    }
+
+    async fn accept(
+        &mut self,
+        fuse_factory: Option<Arc<dyn FuseFactory + Sync + Send + 'static>>,
+    ) -> IoResult<Accepted<Self::Conn>> {
+        let accepted = self.inner.accept(fuse_factory).await?;
+        let remote_addr = accepted.remote_addr.to_string();
+        let conn_id = register(&remote_addr);
+        let mut accepted = accepted.map_conn(|conn| {
+            let fusewire = conn.fusewire();
+            let stream = MtlsStream {
+                state: State::Handshaking(self.tls_acceptor.accept(conn)),
+                remote_addr,
+                conn_id,
+            };
+            StraightStream::new(stream, fusewire)
+        });
+        accepted.http_scheme = Scheme::HTTPS;
+        Ok(accepted)
+    }
+}
+
+enum State {
+    Handshaking(Accept<InnerConn>),
+    Ready(TlsStream<InnerConn>),
+    Failed,
+}
+
+/// 读写时先完成握手，握手成功后登记客户端证书
+pub struct MtlsStream {
+    state: State,
+    remote_addr: String,
+    conn_id: u64,
+}
+
+impl MtlsStream {
+    fn poll_handshake(&mut self, cx: &mut Context<'_>) -> Poll<IoResult<&mut TlsStream<InnerConn>>> {
+        if let State::Handshaking(accept) = &mut self.state {
+            let stream = match ready!(Pin::new(accept).poll(cx)) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    self.state = State::Failed;
+                    return Poll::Ready(Err(e));
+                }
+            };
+            let subject = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(|cert| common_name(cert));
+            match &subject {
+                Some(subject) => debug!("Client certificate {} from {}", subject, self.remote_addr),
+                None => debug!("Client certificate without CN from {}", self.remote_addr),
+            }
+            set_subject(&self.remote_addr, self.conn_id, subject);
+            self.state = State::Ready(stream);
+        }
+        match &mut self.state {
+            State::Ready(stream) => Poll::Ready(Ok(stream)),
+            State::Handshaking(_) => unreachable!(),
+            State::Failed => Poll::Ready(Err(IoError::new(
+                ErrorKind::NotConnected,
+                "TLS handshake failed",
+            ))),
+        }
+    }
+}
+
+impl Drop for MtlsStream {
+    fn drop(&mut self) {
+        unregister(&self.remote_addr, self.conn_id);
+    }
+}
+
+impl AsyncRead for MtlsStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<IoResult<()>> {
+        let stream = ready!(self.get_mut().poll_handshake(cx))?;
+        Pin::new(stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for MtlsStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<IoResult<usize>> {
+        let stream = ready!(self.get_mut().poll_handshake(cx))?;
+        Pin::new(stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        let stream = ready!(self.get_mut().poll_handshake(cx))?;
+        Pin::new(stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        let stream = ready!(self.get_mut().poll_handshake(cx))?;
+        Pin::new(stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_cert_per_connection() {
+        let addr = "192.0.2.1:50000";
+        let old = register(addr);
+        set_subject(addr, old, Some("alice".to_string()));
+        assert_eq!(client_cert_subject(addr).as_deref(), Some("alice"));
+        // 同一地址的新连接没有 CN，不能沿用旧连接的身份
+        let new = register(addr);
+        set_subject(addr, new, None);
+        assert_eq!(client_cert_subject(addr), None);
+        // 旧连接关闭时不能移除新连接的记录
+        set_subject(addr, new, Some("bob".to_string()));
+        unregister(addr, old);
+        assert_eq!(client_cert_subject(addr).as_deref(), Some("bob"));
+        unregister(addr, new);
+        assert_eq!(client_cert_subject(addr), None);
+    }
+}
@@ -0,0 +1,260 @@
+use anyhow::anyhow;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::MANIFEST_FILE;
+use crate::utils::file_mode;
+
+/// 任务包里存放签名的文件名，不计入清单
+pub const SIGNATURE_FILE: &str = ".signature.json";
+
+/// 任务根目录里的忽略规则，语法与 .gitignore 相同，匹配的条目不会打包
+pub const IGNORE_FILE: &str = ".deployignore";
+
+/// 任务包的签名：作者公钥和对清单的签名，均为十六进制
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PackageSignature {
+    pub public_key: String,
+    pub signature: String,
+}
+
+/// 任务包里的一个条目
+#[derive(Debug, Clone, PartialEq)]
+pub enum PackageEntry {
+    /// 普通文件及其本地路径
+    File(PathBuf),
+    Dir,
+    /// 符号链接指向的路径，打包时保存链接本身，不跟随
+    Symlink(PathBuf),
+}
+
+/// 读取任务根目录的 .deployignore，没有该文件时不忽略任何条目
+fn ignore_rules(src_dir: &Path) -> std::io::Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(src_dir);
+    let path = src_dir.join(IGNORE_FILE);
+    if path.is_file()
+        && let Some(e) = builder.add(&path)
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("invalid {}: {}", IGNORE_FILE, e),
+        ));
+    }
+    builder
+        .build()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("invalid {}: {}", IGNORE_FILE, e)))
+}
+
+/// 会打包的条目和被忽略的路径
+type Walked = (Vec<(String, PackageEntry)>, Vec<String>);
+
+/// 按 .deployignore 遍历任务目录，返回（会打包的条目，被忽略的路径），都以 `/` 分隔并排序
+fn walk_package(src_dir: &Path) -> std::io::Result<Walked> {
+    let rules = ignore_rules(src_dir)?;
+    let mut entries = Vec::new();
+    let mut ignored = Vec::new();
+    collect_entries(src_dir, "", &rules, &mut entries, &mut ignored)?;
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    ignored.sort();
+    Ok((entries, ignored))
+}
+
+/// 递归列出任务目录中会被打包的条目，返回（包内路径，条目），包内路径以 `/` 分隔并排序，
+/// 目录排在其内容之前，.deployignore 匹配的条目不包含在内
+pub fn package_entries(src_dir: &Path) -> std::io::Result<Vec<(String, PackageEntry)>> {
+    Ok(walk_package(src_dir)?.0)
+}
+
+/// 任务目录中被 .deployignore 忽略的路径，忽略的目录只列出目录本身
+pub fn ignored_entries(src_dir: &Path) -> std::io::Result<Vec<String>> {
+    Ok(walk_package(src_dir)?.1)
+}
+
+fn collect_entries(
+    dir: &Path,
+    prefix: &str,
+    rules: &Gitignore,
+    entries: &mut Vec<(String, PackageEntry)>,
+    ignored: &mut Vec<String>,
+) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        // file_type 不跟随符号链接
+        let file_type = entry.file_type()?;
+        // 清单和签名文件总是打包，否则任务无法使用
+        let required = prefix.is_empty() && [MANIFEST_FILE, SIGNATURE_FILE].contains(&name.as_str());
+        if !required && rules.matched(&name, file_type.is_dir()).is_ignore() {
+            ignored.push(name);
+        } else if file_type.is_symlink() {
+            entries.push((name, PackageEntry::Symlink(fs::read_link(entry.path())?)));
+        } else if file_type.is_dir() {
+            collect_entries(&entry.path(), &format!("{}/", name), rules, entries, ignored)?;
+            entries.push((name, PackageEntry::Dir));
+        } else if file_type.is_file() {
+            entries.push((name, PackageEntry::File(entry.path())));
+        }
+    }
+    Ok(())
+}
+
+/// 任务包清单：文件每行为 SHA-256、权限和包内路径，目录和符号链接各占一行，签名针对的就是它。
+/// 权限也在签名范围内，服务端改不了私钥的读权限或数据文件的执行权限
+pub fn build_manifest(src_dir: &Path) -> std::io::Result<String> {
+    manifest_of(src_dir, package_entries(src_dir)?)
+}
+
+fn manifest_of(src_dir: &Path, entries: Vec<(String, PackageEntry)>) -> std::io::Result<String> {
+    let mut manifest = String::new();
+    let mode = |name: &str| -> std::io::Result<u32> { Ok(file_mode(&fs::symlink_metadata(src_dir.join(name))?)) };
+    for (name, entry) in entries {
+        match entry {
+            PackageEntry::File(_) if name == SIGNATURE_FILE => {}
+            PackageEntry::File(path) => {
+                let digest = Sha256::digest(fs::read(&path)?);
+                manifest.push_str(&format!("{:x}  {:04o}  {}\n", digest, mode(&name)?, name));
+            }
+            PackageEntry::Dir => manifest.push_str(&format!("dir  {:04o}  {}/\n", mode(&name)?, name)),
+            PackageEntry::Symlink(target) => {
+                manifest.push_str(&format!("link  {} -> {}\n", name, target.to_string_lossy()))
+            }
+        }
+    }
+    Ok(manifest)
+}
+
+/// 任务内容的哈希，即清单的 SHA-256，签名文件不影响结果
+pub fn content_hash(src_dir: &Path) -> std::io::Result<String> {
+    Ok(format!("{:x}", Sha256::digest(build_manifest(src_dir)?)))
+}
+
+/// 生成新的作者密钥，返回（私钥，公钥）的十六进制
+pub fn generate_author_key() -> (String, String) {
+    let key = SigningKey::generate(&mut rand_core::OsRng);
+    (
+        hex::encode(key.to_bytes()),
+        hex::encode(key.verifying_key().to_bytes()),
+    )
+}
+
+fn parse_signing_key(secret: &str) -> anyhow::Result<SigningKey> {
+    let bytes: [u8; 32] = hex::decode(secret.trim())?
+        .try_into()
+        .map_err(|_| anyhow!("Author key must be 32 bytes"))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+fn parse_verifying_key(public: &str) -> anyhow::Result<VerifyingKey> {
+    let bytes: [u8; 32] = hex::decode(public.trim())?
+        .try_into()
+        .map_err(|_| anyhow!("Public key must be 32 bytes"))?;
+    Ok(VerifyingKey::from_bytes(&bytes)?)
+}
+
+/// 由私钥推导出公钥的十六进制
+pub fn author_public_key(secret: &str) -> anyhow::Result<String> {
+    Ok(hex::encode(parse_signing_key(secret)?.verifying_key().to_bytes()))
+}
+
+/// 用作者私钥对任务目录的清单签名
+pub fn sign_package(src_dir: &Path, secret: &str) -> anyhow::Result<PackageSignature> {
+    let key = parse_signing_key(secret)?;
+    let manifest = build_manifest(src_dir)?;
+    Ok(PackageSignature {
+        public_key: hex::encode(key.verifying_key().to_bytes()),
+        signature: hex::encode(key.sign(manifest.as_bytes()).to_bytes()),
+    })
+}
+
+/// 校验解压后的任务目录：签名文件存在、公钥在信任列表里且签名与清单匹配，返回签名者的名字
+pub fn verify_package(dir: &Path, trusted_keys: &HashMap<String, String>) -> anyhow::Result<String> {
+    let signature_path = dir.join(SIGNATURE_FILE);
+    if !signature_path.exists() {
+        return Err(anyhow!("Package is not signed"));
+    }
+    let package_signature: PackageSignature =
+        serde_json::from_str(&fs::read_to_string(signature_path)?)?;
+    let (signer, _) = trusted_keys
+        .iter()
+        .find(|(_, key)| key.trim().eq_ignore_ascii_case(&package_signature.public_key))
+        .ok_or(anyhow!(
+            "Package is signed by untrusted key {}",
+            package_signature.public_key
+        ))?;
+    let key = parse_verifying_key(&package_signature.public_key)?;
+    let signature = Signature::from_slice(&hex::decode(&package_signature.signature)?)?;
+    // 解压后的目录里不应出现被忽略的条目，它们不在清单里，也就没有经过签名
+    let (entries, ignored) = walk_package(dir)?;
+    if let Some(name) = ignored.first() {
+        return Err(anyhow!("Package contains {}, which is excluded by {}", name, IGNORE_FILE));
+    }
+    let manifest = manifest_of(dir, entries)?;
+    key.verify(manifest.as_bytes(), &signature)
+        .map_err(|_| anyhow!("Package signature does not match its contents"))?;
+    Ok(signer.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify_package() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("run.sh"), "echo hi").unwrap();
+        let (secret, public) = generate_author_key();
+        let signature = sign_package(dir, &secret).unwrap();
+        let mut trusted = HashMap::new();
+        assert!(verify_package(dir, &trusted).is_err());
+        fs::write(dir.join(SIGNATURE_FILE), serde_json::to_string(&signature).unwrap()).unwrap();
+        assert!(verify_package(dir, &trusted).is_err());
+        trusted.insert("tom".to_string(), public);
+        assert_eq!(verify_package(dir, &trusted).unwrap(), "tom");
+        fs::write(dir.join("run.sh"), "rm -rf /").unwrap();
+        assert!(verify_package(dir, &trusted).is_err());
+        fs::write(dir.join("run.sh"), "echo hi").unwrap();
+        #[cfg(target_family = "unix")]
+        {
+            // 只改权限也会让签名失效
+            let mode = file_mode(&fs::metadata(dir.join("run.sh")).unwrap());
+            crate::utils::set_mode(&dir.join("run.sh"), 0o777).unwrap();
+            assert!(verify_package(dir, &trusted).is_err());
+            crate::utils::set_mode(&dir.join("run.sh"), mode).unwrap();
+            assert_eq!(verify_package(dir, &trusted).unwrap(), "tom");
+        }
+        fs::create_dir_all(dir.join("files/etc")).unwrap();
+        fs::write(dir.join("files/etc/site.conf"), "listen 80;").unwrap();
+        assert!(verify_package(dir, &trusted).is_err());
+    }
+
+    #[test]
+    fn test_deployignore() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join(".git/objects")).unwrap();
+        fs::create_dir_all(dir.join("files/build")).unwrap();
+        for file in ["config.toml", "run.sh", "run.sh.swp", "keep.swp", ".git/HEAD", "files/build/out.o", "files/a.conf"] {
+            fs::write(dir.join(file), "x").unwrap();
+        }
+        fs::write(dir.join(IGNORE_FILE), "*.swp\n!keep.swp\n.git/\nbuild/\nconfig.toml\n").unwrap();
+        let names: Vec<String> = package_entries(dir).unwrap().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, [IGNORE_FILE, "config.toml", "files", "files/a.conf", "keep.swp", "run.sh"]);
+        assert_eq!(ignored_entries(dir).unwrap(), [".git", "files/build", "run.sh.swp"]);
+        let (secret, public) = generate_author_key();
+        let signature = sign_package(dir, &secret).unwrap();
+        fs::write(dir.join(SIGNATURE_FILE), serde_json::to_string(&signature).unwrap()).unwrap();
+        let trusted = HashMap::from([("tom".to_string(), public)]);
+        // 被忽略的文件不在清单里，解压后的包里出现时拒绝
+        assert!(verify_package(dir, &trusted).is_err());
+        fs::remove_dir_all(dir.join(".git")).unwrap();
+        fs::remove_dir_all(dir.join("files/build")).unwrap();
+        fs::remove_file(dir.join("run.sh.swp")).unwrap();
+        assert_eq!(verify_package(dir, &trusted).unwrap(), "tom");
+    }
+}
@@ -0,0 +1,287 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// 参数传给 run.sh 时环境变量名的前缀
+pub const PARAM_ENV_PREFIX: &str = "DEPLOY_PARAM_";
+
+/// 参数类型，secret 在输入时不回显
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamKind {
+    #[default]
+    String,
+    Int,
+    Bool,
+    Enum,
+    Secret,
+}
+
+/// config.toml 里写的默认值，可以是字符串、整数或布尔值
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ParamValue {
+    Bool(bool),
+    Int(i64),
+    String(String),
+}
+
+impl std::fmt::Display for ParamValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParamValue::Bool(b) => write!(f, "{}", b),
+            ParamValue::Int(i) => write!(f, "{}", i),
+            ParamValue::String(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// 任务 config.toml 中 `[[params]]` 声明的参数
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Param {
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub kind: ParamKind,
+    pub default: Option<ParamValue>,
+    #[serde(default)]
+    pub description: String,
+    /// 校验用的正则，需要匹配整个值
+    pub pattern: Option<String>,
+    /// enum 类型可选的值
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub values: Vec<String>,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ParamError {
+    #[error("invalid parameter name `{0}`, use letters, digits and `_`")]
+    InvalidName(String),
+    #[error("parameter `{0}` is declared twice")]
+    Duplicate(String),
+    #[error("parameter `{0}` has an invalid pattern: {1}")]
+    InvalidPattern(String, String),
+    #[error("enum parameter `{0}` has no values")]
+    NoValues(String),
+    #[error("parameter `{0}` has an invalid default: {1}")]
+    InvalidDefault(String, Box<ParamError>),
+    #[error("`{1}` is not a valid {2:?} for parameter `{0}`")]
+    InvalidValue(String, String, ParamKind),
+    #[error("`{1}` is not one of {2:?} for parameter `{0}`")]
+    NotInEnum(String, String, Vec<String>),
+    #[error("`{1}` does not match the pattern of parameter `{0}`")]
+    PatternMismatch(String, String),
+    #[error("unknown parameter `{0}`")]
+    Unknown(String),
+    #[error("parameter `{0}` has no default and no value was given")]
+    Missing(String),
+}
+
+impl Param {
+    /// 对应的环境变量名，如 `db_host` 对应 `DEPLOY_PARAM_DB_HOST`
+    pub fn env_name(&self) -> String {
+        format!("{}{}", PARAM_ENV_PREFIX, self.name.to_ascii_uppercase())
+    }
+
+    fn pattern(&self) -> Result<Option<Regex>, ParamError> {
+        self.pattern
+            .as_deref()
+            .map(|p| Regex::new(&format!("^(?:{})$", p)))
+            .transpose()
+            .map_err(|e| ParamError::InvalidPattern(self.name.clone(), e.to_string()))
+    }
+
+    /// 检查声明本身：名字、正则、enum 的可选值和默认值
+    pub fn check(&self) -> Result<(), ParamError> {
+        if !crate::is_valid_secret_name(&self.name) {
+            return Err(ParamError::InvalidName(self.name.clone()));
+        }
+        self.pattern()?;
+        if self.kind == ParamKind::Enum && self.values.is_empty() {
+            return Err(ParamError::NoValues(self.name.clone()));
+        }
+        if let Some(default) = &self.default {
+            self.validate(&default.to_string())
+                .map_err(|e| ParamError::InvalidDefault(self.name.clone(), Box::new(e)))?;
+        }
+        Ok(())
+    }
+
+    /// 校验输入的值，返回规范化后的值（布尔值统一为 true/false）
+    pub fn validate(&self, value: &str) -> Result<String, ParamError> {
+        let invalid = || ParamError::InvalidValue(self.name.clone(), value.to_string(), self.kind);
+        let value = match self.kind {
+            ParamKind::Int => value.trim().parse::<i64>().map_err(|_| invalid())?.to_string(),
+            ParamKind::Bool => match value.trim().to_ascii_lowercase().as_str() {
+                "true" | "yes" | "y" | "1" => "true".to_string(),
+                "false" | "no" | "n" | "0" => "false".to_string(),
+                _ => return Err(invalid()),
+            },
+            ParamKind::Enum => {
+                if !self.values.iter().any(|v| v == value) {
+                    return Err(ParamError::NotInEnum(
+                        self.name.clone(),
+                        value.to_string(),
+                        self.values.clone(),
+                    ));
+                }
+                value.to_string()
+            }
+            ParamKind::String | ParamKind::Secret => value.to_string(),
+        };
+        if let Some(pattern) = self.pattern()?
+            && !pattern.is_match(&value)
+        {
+            return Err(ParamError::PatternMismatch(self.name.clone(), value));
+        }
+        Ok(value)
+    }
+}
+
+/// 检查一组参数声明
+pub fn check_params(params: &[Param]) -> Result<(), ParamError> {
+    for (i, param) in params.iter().enumerate() {
+        param.check()?;
+        if params[..i].iter().any(|p| p.name.eq_ignore_ascii_case(&param.name)) {
+            return Err(ParamError::Duplicate(param.name.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// 确定每个参数的值并返回环境变量：优先用 provided 里给出的值，否则调用 prompt 询问，
+/// prompt 返回 None 时使用默认值。输入不合法时带着错误再次调用 prompt，由调用方决定提示还是放弃；
+/// 没有默认值的参数 prompt 返回 None 时带着 Missing 再问一次，仍然没有值就返回 Missing，不会无限询问
+pub fn resolve_params<F>(
+    params: &[Param],
+    provided: &HashMap<String, String>,
+    mut prompt: F,
+) -> anyhow::Result<HashMap<String, String>>
+where
+    F: FnMut(&Param, Option<&ParamError>) -> anyhow::Result<Option<String>>,
+{
+    if let Some(name) = provided.keys().find(|k| !params.iter().any(|p| &p.name == *k)) {
+        return Err(ParamError::Unknown(name.clone()).into());
+    }
+    let mut envs = HashMap::new();
+    for param in params {
+        let value = match provided.get(&param.name) {
+            Some(value) => param.validate(value)?,
+            None => {
+                // 输入不合法时重新询问
+                let mut error = None;
+                loop {
+                    let input = match prompt(param, error.as_ref())? {
+                        Some(input) => input,
+                        None => match &param.default {
+                            Some(default) => default.to_string(),
+                            None if matches!(error, Some(ParamError::Missing(_))) => {
+                                return Err(ParamError::Missing(param.name.clone()).into());
+                            }
+                            None => {
+                                error = Some(ParamError::Missing(param.name.clone()));
+                                continue;
+                            }
+                        },
+                    };
+                    match param.validate(&input) {
+                        Ok(value) => break value,
+                        Err(e) => error = Some(e),
+                    }
+                }
+            }
+        };
+        envs.insert(param.env_name(), value);
+    }
+    Ok(envs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> Vec<Param> {
+        toml::from_str::<HashMap<String, Vec<Param>>>(
+            r#"
+            [[params]]
+            name = "port"
+            type = "int"
+            default = 8080
+
+            [[params]]
+            name = "env"
+            type = "enum"
+            values = ["prod", "dev"]
+
+            [[params]]
+            name = "debug"
+            type = "bool"
+            default = false
+
+            [[params]]
+            name = "host"
+            pattern = "[a-z.]+"
+            "#,
+        )
+        .unwrap()
+        .remove("params")
+        .unwrap()
+    }
+
+    #[test]
+    fn test_validate_params() {
+        let params = params();
+        assert_eq!(check_params(&params), Ok(()));
+        assert_eq!(params[0].validate("443"), Ok("443".to_string()));
+        assert!(params[0].validate("http").is_err());
+        assert!(params[1].validate("staging").is_err());
+        assert_eq!(params[2].validate("yes"), Ok("true".to_string()));
+        assert!(params[3].validate("example.com").is_ok());
+        assert!(params[3].validate("example.com; rm -rf /").is_err());
+        let mut bad = params[0].clone();
+        bad.default = Some(ParamValue::String("http".to_string()));
+        assert!(matches!(bad.check(), Err(ParamError::InvalidDefault(..))));
+    }
+
+    #[test]
+    fn test_resolve_params() {
+        let params = params();
+        let provided = HashMap::from([("env".to_string(), "prod".to_string())]);
+        let mut asked = Vec::new();
+        let envs = resolve_params(&params, &provided, |p, error| {
+            asked.push((p.name.clone(), error.is_some()));
+            Ok(match (p.name.as_str(), error) {
+                // 第一次给出不合法的值，带着错误再问一次
+                ("host", None) => Some("example.com; rm -rf /".to_string()),
+                ("host", Some(_)) => Some("example.com".to_string()),
+                _ => None,
+            })
+        })
+        .unwrap();
+        let asked: Vec<_> = asked.iter().map(|(name, retry)| (name.as_str(), *retry)).collect();
+        assert_eq!(asked, [("port", false), ("debug", false), ("host", false), ("host", true)]);
+        assert_eq!(envs["DEPLOY_PARAM_PORT"], "8080");
+        assert_eq!(envs["DEPLOY_PARAM_ENV"], "prod");
+        assert_eq!(envs["DEPLOY_PARAM_DEBUG"], "false");
+        let unknown = HashMap::from([("nope".to_string(), "1".to_string())]);
+        assert!(resolve_params(&params, &unknown, |_, _| Ok(None)).is_err());
+    }
+
+    #[test]
+    fn test_resolve_params_missing() {
+        // host 没有默认值，prompt 一直不给值时报错而不是一直询问
+        let params = params();
+        let provided = HashMap::from([("env".to_string(), "prod".to_string())]);
+        let mut asked = 0;
+        let err = resolve_params(&params, &provided, |p, _| {
+            if p.name == "host" {
+                asked += 1;
+            }
+            Ok(None)
+        })
+        .unwrap_err();
+        assert!(matches!(err.downcast_ref::<ParamError>(), Some(ParamError::Missing(name)) if name == "host"));
+        assert_eq!(asked, 2);
+    }
+}
@@ -0,0 +1,182 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use std::cmp::Ordering;
+use std::fs;
+
+/// 任务 config.toml 中 `[platform]` 声明的运行平台，每项都可以写一个值或一个列表，不写表示不限
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Platform {
+    /// 操作系统，取值同 Rust 的 `std::env::consts::OS`，如 linux、macos、windows
+    #[serde(default, deserialize_with = "one_or_many", skip_serializing_if = "Vec::is_empty")]
+    pub os: Vec<String>,
+    /// CPU 架构，如 x86_64、aarch64
+    #[serde(default, deserialize_with = "one_or_many", skip_serializing_if = "Vec::is_empty")]
+    pub arch: Vec<String>,
+    /// 发行版，匹配 /etc/os-release 的 ID 或 ID_LIKE，如 debian、alpine
+    #[serde(default, deserialize_with = "one_or_many", skip_serializing_if = "Vec::is_empty")]
+    pub distro: Vec<String>,
+    /// 发行版的最低版本，与 /etc/os-release 的 VERSION_ID 按数字逐段比较
+    pub min_version: Option<String>,
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+/// 本机的平台信息
+#[derive(Debug, Clone, Default)]
+pub struct HostFacts {
+    pub os: String,
+    pub arch: String,
+    /// /etc/os-release 的 ID 和 ID_LIKE
+    pub distro: Vec<String>,
+    pub version: Option<String>,
+}
+
+impl HostFacts {
+    /// 检测本机的平台信息
+    pub fn detect() -> Self {
+        let os_release = fs::read_to_string("/etc/os-release").unwrap_or_default();
+        let mut facts = HostFacts::from_os_release(&os_release);
+        facts.os = std::env::consts::OS.to_string();
+        facts.arch = std::env::consts::ARCH.to_string();
+        facts
+    }
+
+    /// 解析 os-release 格式的内容
+    pub fn from_os_release(content: &str) -> Self {
+        let mut facts = HostFacts::default();
+        for line in content.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim().trim_matches('"').trim_matches('\'');
+            match key.trim() {
+                "ID" => facts.distro.insert(0, value.to_ascii_lowercase()),
+                "ID_LIKE" => facts
+                    .distro
+                    .extend(value.split_whitespace().map(|s| s.to_ascii_lowercase())),
+                "VERSION_ID" => facts.version = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        facts
+    }
+}
+
+impl std::fmt::Display for HostFacts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.os, self.arch)?;
+        if let Some(distro) = self.distro.first() {
+            write!(f, " {}", distro)?;
+            if let Some(version) = &self.version {
+                write!(f, " {}", version)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 按数字逐段比较版本号，如 11 < 11.2 < 12
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parse = |v: &str| -> Vec<u64> {
+        v.split(['.', '-'])
+            .map(|part| part.parse().unwrap_or(0))
+            .collect()
+    };
+    let (a, b) = (parse(a), parse(b));
+    for i in 0..a.len().max(b.len()) {
+        let ordering = a.get(i).unwrap_or(&0).cmp(b.get(i).unwrap_or(&0));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+impl Platform {
+    /// 检查本机是否满足要求，不满足时返回所有不满足的原因
+    pub fn check(&self, host: &HostFacts) -> Result<(), Vec<String>> {
+        let mut reasons = Vec::new();
+        let matches = |allowed: &[String], value: &str| {
+            allowed.is_empty() || allowed.iter().any(|a| a.eq_ignore_ascii_case(value))
+        };
+        if !matches(&self.os, &host.os) {
+            reasons.push(format!("requires os {}, host is {}", self.os.join("/"), host.os));
+        }
+        if !matches(&self.arch, &host.arch) {
+            reasons.push(format!("requires arch {}, host is {}", self.arch.join("/"), host.arch));
+        }
+        if !self.distro.is_empty() && !host.distro.iter().any(|d| matches(&self.distro, d)) {
+            reasons.push(format!(
+                "requires distro {}, host is {}",
+                self.distro.join("/"),
+                host.distro.first().map(|s| s.as_str()).unwrap_or("unknown")
+            ));
+        }
+        if let Some(min_version) = &self.min_version {
+            match &host.version {
+                Some(version) if compare_versions(version, min_version) != Ordering::Less => {}
+                version => reasons.push(format!(
+                    "requires version {} or later, host is {}",
+                    min_version,
+                    version.as_deref().unwrap_or("unknown")
+                )),
+            }
+        }
+        if reasons.is_empty() { Ok(()) } else { Err(reasons) }
+    }
+
+    /// 任务目录里必须有的脚本：Windows 需要 run.bat，其它系统需要 run.sh
+    pub fn required_scripts(&self) -> Vec<&'static str> {
+        if self.os.is_empty() {
+            return vec![if cfg!(target_family = "windows") { "run.bat" } else { "run.sh" }];
+        }
+        let mut scripts = Vec::new();
+        if self.os.iter().any(|os| os.eq_ignore_ascii_case("windows")) {
+            scripts.push("run.bat");
+        }
+        if self.os.iter().any(|os| !os.eq_ignore_ascii_case("windows")) {
+            scripts.push("run.sh");
+        }
+        scripts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_platform_check() {
+        let mut host = HostFacts::from_os_release(
+            "NAME=\"Ubuntu\"\nID=ubuntu\nID_LIKE=debian\nVERSION_ID=\"22.04\"\n",
+        );
+        host.os = "linux".to_string();
+        host.arch = "x86_64".to_string();
+        assert_eq!(host.distro, ["ubuntu", "debian"]);
+        let platform: Platform = toml::from_str(
+            "os = \"linux\"\narch = [\"x86_64\", \"aarch64\"]\ndistro = \"debian\"\nmin_version = \"20.04\"",
+        )
+        .unwrap();
+        assert_eq!(platform.check(&host), Ok(()));
+        let alpine: Platform = toml::from_str("distro = \"alpine\"\narch = \"aarch64\"").unwrap();
+        assert_eq!(alpine.check(&host).unwrap_err().len(), 2);
+        let newer = Platform {
+            min_version: Some("22.10".to_string()),
+            ..Default::default()
+        };
+        assert!(newer.check(&host).is_err());
+        assert_eq!(compare_versions("11", "11.0"), Ordering::Equal);
+        assert_eq!(compare_versions("3.9", "3.18"), Ordering::Less);
+    }
+}
@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use thiserror::Error;
+
+use crate::{ParamError, ParamValue, Task, TaskRefError, find_task};
+
+const MAX_PROFILE_NAME_LEN: usize = 64;
+
+/// 一组按顺序运行的任务，例如初始化一台新主机需要的所有任务
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Profile {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub tasks: Vec<ProfileTask>,
+}
+
+/// profile 里的一个任务及其参数值
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProfileTask {
+    /// 任务名、完整 uuid 或唯一的 uuid 前缀
+    pub task: String,
+    /// 参数值，没有给出的参数运行时再询问
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub params: BTreeMap<String, ParamValue>,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ProfileError {
+    #[error("invalid profile name `{0}`, only ASCII letters, digits, `-` and `_` are allowed")]
+    InvalidName(String),
+    #[error("profile `{0}` has no tasks")]
+    Empty(String),
+    #[error("profile `{0}` not found")]
+    NotFound(String),
+    #[error(transparent)]
+    Task(#[from] TaskRefError),
+    #[error("task `{0}`: {1}")]
+    Param(String, ParamError),
+}
+
+pub fn is_valid_profile_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_PROFILE_NAME_LEN
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl ProfileTask {
+    /// 转成 `deploy get --set` 形式的参数值
+    pub fn provided(&self) -> HashMap<String, String> {
+        self.params
+            .iter()
+            .map(|(name, value)| (name.clone(), value.to_string()))
+            .collect()
+    }
+}
+
+impl Profile {
+    /// 检查名字，以及每个任务都存在、参数都已声明且值合法
+    pub fn check(&self, tasks: &[Task]) -> Result<(), ProfileError> {
+        if !is_valid_profile_name(&self.name) {
+            return Err(ProfileError::InvalidName(self.name.clone()));
+        }
+        if self.tasks.is_empty() {
+            return Err(ProfileError::Empty(self.name.clone()));
+        }
+        for member in &self.tasks {
+            let task = find_task(tasks, &member.task)?;
+            for (name, value) in &member.params {
+                let param = task
+                    .params
+                    .iter()
+                    .find(|p| &p.name == name)
+                    .ok_or_else(|| ProfileError::Param(task.name.clone(), ParamError::Unknown(name.clone())))?;
+                param
+                    .validate(&value.to_string())
+                    .map_err(|e| ProfileError::Param(task.name.clone(), e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 是否包含 target 任务
+    pub fn uses(&self, tasks: &[Task], target: &Task) -> bool {
+        self.tasks.iter().any(|member| {
+            find_task(tasks, &member.task).is_ok_and(|t| t.uuid == target.uuid && t.name == target.name)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_check() {
+        let tasks: Vec<Task> = ["base", "nginx"]
+            .iter()
+            .map(|name| {
+                toml::from_str(&format!(
+                    "uuid = \"{}\"\nname = \"{}\"\ndescription = \"\"\n[[params]]\nname = \"port\"\ntype = \"int\"\n",
+                    uuid::Uuid::new_v4(),
+                    name
+                ))
+                .unwrap()
+            })
+            .collect();
+        let mut profile: Profile = toml::from_str(
+            "name = \"vps\"\n[[tasks]]\ntask = \"base\"\n[[tasks]]\ntask = \"nginx\"\nparams = { port = 443 }\n",
+        )
+        .unwrap();
+        assert_eq!(profile.check(&tasks), Ok(()));
+        assert_eq!(profile.tasks[1].provided()["port"], "443");
+        assert!(profile.uses(&tasks, &tasks[0]));
+        profile.tasks[1].params.insert("port".to_string(), ParamValue::String("http".to_string()));
+        assert!(matches!(profile.check(&tasks), Err(ProfileError::Param(..))));
+        profile.tasks[1].params.clear();
+        profile.tasks.push(ProfileTask {
+            task: "redis".to_string(),
+            params: BTreeMap::new(),
+        });
+        assert!(matches!(profile.check(&tasks), Err(ProfileError::Task(TaskRefError::NotFound(_)))));
+        profile.name = "../vps".to_string();
+        assert!(matches!(profile.check(&tasks), Err(ProfileError::InvalidName(_))));
+    }
+}
@@ -3,12 +3,24 @@ use serde::Serialize;
 use serde_json::{json, Value};
 use thiserror::Error;
 
+use deploycli::{ProfileError, TaskIdError, TaskRefError, UnpackError};
+
 #[derive(Error, Debug)]
 pub enum AppError {
     #[error("error:`{0}`")]
     AnyHow(#[from] anyhow::Error),
     #[error("io error:`{0}`")]
     Io(#[from] std::io::Error),
+    #[error("invalid task id:`{0}`")]
+    InvalidTaskId(#[from] TaskIdError),
+    #[error("bad request:`{0}`")]
+    BadRequest(String),
+    #[error("unpack error:`{0}`")]
+    Unpack(#[from] UnpackError),
+    #[error("task not found:`{0}`")]
+    TaskRef(#[from] TaskRefError),
+    #[error("profile error:`{0}`")]
+    Profile(#[from] ProfileError),
 }
 
 pub type AppResult = Result<Success, AppError>;
@@ -41,6 +53,42 @@ impl Scribe for AppError {
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(format!("IO Error: {}", e)),
             ),
+            AppError::InvalidTaskId(e) => res.stuff(
+                StatusCode::BAD_REQUEST,
+                Json(format!("Bad Request: {}", e)),
+            ),
+            AppError::BadRequest(e) => res.stuff(
+                StatusCode::BAD_REQUEST,
+                Json(format!("Bad Request: {}", e)),
+            ),
+            AppError::Unpack(UnpackError::Io(e)) => res.stuff(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(format!("IO Error: {}", e)),
+            ),
+            AppError::Unpack(e) if e.is_limit_exceeded() => res.stuff(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json(format!("Payload Too Large: {}", e)),
+            ),
+            AppError::Unpack(e) => res.stuff(
+                StatusCode::BAD_REQUEST,
+                Json(format!("Bad Request: {}", e)),
+            ),
+            AppError::TaskRef(e @ TaskRefError::NotFound(_)) => res.stuff(
+                StatusCode::NOT_FOUND,
+                Json(format!("Not Found: {}", e)),
+            ),
+            AppError::TaskRef(e) => res.stuff(
+                StatusCode::BAD_REQUEST,
+                Json(format!("Bad Request: {}", e)),
+            ),
+            AppError::Profile(e @ ProfileError::NotFound(_)) => res.stuff(
+                StatusCode::NOT_FOUND,
+                Json(format!("Not Found: {}", e)),
+            ),
+            AppError::Profile(e) => res.stuff(
+                StatusCode::BAD_REQUEST,
+                Json(format!("Bad Request: {}", e)),
+            ),
         }
     }
 }
\ No newline at end of file
@@ -1,37 +1,112 @@
 use anyhow::anyhow;
-use log::{debug, error};
+use log::{debug, error, info, warn};
 use salvo::prelude::*;
 use serde_json::json;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
+use crate::auth::{Identity, Scope, authenticate, authenticate_cert, authenticate_signed, signing_token};
 use crate::config::CFG;
 use crate::db::DB;
-use crate::result::AppResult;
-use crate::utils::{create_zip, unpack_zip};
-use crate::utils::Task;
+use crate::limiter::{FAILURES, RateLimit, client_ip, too_many_requests};
+use crate::mtls::client_cert_subject;
+use crate::result::{AppError, AppResult};
+use crate::store::{self, STORE};
+use crate::vault::VAULT;
+use deploycli::{check_dependencies, load_manifest, create_zip, dependents, unpack_zip_with_limits};
+use deploycli::{Profile, ProfileError};
+use deploycli::{AUDIT_ACTIONS, AuditFilter, AuditRecord, TaskRefError, content_hash, find_task, unix_now};
+use deploycli::{MAX_SECRET_SIZE, SecretInfo, TaskQuery, is_valid_secret_name};
+use deploycli::{PackageSignature, SIGNATURE_FILE, SignedHeader, Task, TaskId, TaskVersion};
 
+/// 从表单的 name 和 uuid 字段解析任务标识
+async fn task_id_from_form(req: &mut Request) -> Result<TaskId, AppError> {
+    let task_name = req.form::<String>("name").await.ok_or(anyhow!("Task name not found"))?;
+    let task_uuid = req.form::<String>("uuid").await.ok_or(anyhow!("Task UUID not found"))?;
+    Ok(TaskId::parse(&task_name, &task_uuid)?)
+}
 
 #[handler]
-async fn list_tasks() -> AppResult {
-    let tasks = DB.get_all_tasks()?;
+async fn list_tasks(req: &mut Request) -> AppResult {
+    let query = TaskQuery {
+        tag: req.query("tag"),
+        category: req.query("category"),
+        q: req.query("q"),
+        sort: req.query("sort"),
+        limit: req.query("limit"),
+        offset: req.query("offset"),
+    };
+    query.check().map_err(AppError::BadRequest)?;
+    let tasks = DB.search_tasks(&query)?;
     Ok(tasks.into())
 }
 
+/// 注册过的路由模式，`[limits.rates]` 的键必须是其中之一
+static ROUTE_PATTERNS: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+
+/// 每个请求独立的临时目录，用于解压上传的包或还原要下载的版本
+fn work_dir(task_id: &TaskId) -> PathBuf {
+    std::env::temp_dir().join(format!("deploycli-{}-{}", task_id, uuid::Uuid::new_v4()))
+}
+
+/// 从路径参数 id 解析任务标识，id 可以是 `<name>-<uuid>`、任务名、完整 uuid 或唯一的 uuid 前缀
+fn task_id_from_param(req: &Request) -> Result<TaskId, AppError> {
+    let id = req.param::<String>("id").ok_or(anyhow!("Task id not found"))?;
+    if let Ok(task_id) = TaskId::from_dir_name(&id) {
+        return Ok(task_id);
+    }
+    let tasks = DB.find_task_candidates(&id)?;
+    Ok(find_task(&tasks, &id)?.id()?)
+}
+
+#[handler]
+async fn get_task(req: &mut Request) -> AppResult {
+    let task_id = task_id_from_param(req)?;
+    let task = DB
+        .get_task(&task_id)
+        .map_err(|_| TaskRefError::NotFound(task_id.to_string()))?;
+    Ok(task.into())
+}
+
+/// 当前调用方的名字
+fn actor(depot: &Depot) -> String {
+    depot
+        .obtain::<Identity>()
+        .map(|identity| identity.name.clone())
+        .unwrap_or_default()
+}
+
 #[handler]
 async fn download_task(req: &mut Request, res: &mut Response) -> AppResult {
-    let task_name = req.form::<String>("name").await.ok_or(anyhow!("Task name not found"))?;
-    let task_uuid = req.form::<String>("uuid").await.ok_or(anyhow!("Task UUID not found"))?;
+    let task_id = task_id_from_form(req).await?;
     let task_md5 = req.form::<String>("md5").await.ok_or(anyhow!("Task md5 not found"))?;
-    let task = format!("{}-{}", task_name, task_uuid);
-    let task_dir = Path::new("./tasks").join(&task);
-    // 检查任务目录是否存在
-    if !task_dir.exists() || !task_dir.is_dir() {
-        return Err(anyhow!("Task not found").into());
-    }
-    // 创建压缩包路径
-    let zip_path = Path::new("./tasks").join(format!("{}.zip", task));
-    create_zip(&task_dir, &zip_path)?;
+    // 指定了版本时打包该版本，否则打包最新版本；版本号不合法时报错，不能悄悄换成最新版本
+    let version = match req.form::<String>("version").await {
+        Some(version) => Some(
+            version
+                .parse::<u32>()
+                .map_err(|_| AppError::BadRequest(format!("invalid version `{}`", version)))?,
+        ),
+        None => None,
+    };
+    let snapshot = match version {
+        Some(version) => store::load_version(&task_id, version)?,
+        None => store::load_latest(&task_id)?,
+    }
+    .ok_or_else(|| TaskRefError::NotFound(task_id.to_string()))?;
+    // 从 blob 还原到临时目录再打包，权限和修改时间都来自快照，同一版本每次打出的包相同
+    let task_dir = work_dir(&task_id);
+    let zip_path = task_dir.with_extension("zip");
+    let packed = STORE
+        .checkout(&snapshot, &task_dir)
+        .and_then(|_| create_zip(&task_dir, &zip_path));
+    if let Err(e) = fs::remove_dir_all(&task_dir) {
+        error!("Failed to delete {}: {}", task_dir.display(), e);
+    }
+    packed?;
     // 计算压缩包的md5值
     let md5 = md5::compute(tokio::fs::read(zip_path.clone()).await?);
     // 如果md5值相同，则不返回文件
@@ -57,72 +132,602 @@ async fn download_task(req: &mut Request, res: &mut Response) -> AppResult {
     Ok(0.into())
 }
 
+/// 把上传的压缩包解压到临时目录并校验，返回其中的任务
+fn stage_version(zip: &Path, version_dir: &Path, signature: Option<&str>, task_id: &TaskId) -> Result<Task, AppError> {
+    // 解压到临时目录，超出限制时返回错误
+    unpack_zip_with_limits(zip, version_dir, &CFG.unpack)?;
+    // 保存作者签名，随任务包一起下发给客户端校验
+    if let Some(signature) = signature {
+        let signature: PackageSignature = serde_json::from_str(signature)
+            .map_err(|e| anyhow!("Invalid package signature: {}", e))?;
+        fs::write(
+            version_dir.join(SIGNATURE_FILE),
+            serde_json::to_string(&signature).map_err(|e| anyhow!(e))?,
+        )?;
+    }
+    // 按严格格式校验目标里的config.toml，并检查声明的脚本都在包里
+    let task = read_task(version_dir)?;
+    if task.id()? != *task_id {
+        return Err(anyhow!("config.toml does not match the uploaded task {}", task_id).into());
+    }
+    check_task(&task)?;
+    Ok(task)
+}
+
+/// 检查任务替换进现有任务后依赖是否都存在且无环
+fn check_task(task: &Task) -> Result<(), AppError> {
+    let task_id = task.id()?;
+    let mut tasks = DB.get_all_tasks()?;
+    tasks.retain(|t| t.id().ok().as_ref() != Some(&task_id));
+    tasks.push(task.clone());
+    check_dependencies(&tasks).map_err(|e| AppError::BadRequest(e.to_string()))?;
+    Ok(())
+}
+
+/// 在阻塞线程池里执行要等存储锁或遍历磁盘的操作，回收期间不会占住异步运行时的工作线程
+async fn blocking<T, F>(f: F) -> Result<T, AppError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, AppError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f).await.map_err(|e| anyhow!(e))?
+}
+
+/// 读取并校验目录里的 config.toml
+fn read_task(dir: &Path) -> Result<Task, AppError> {
+    load_manifest(dir).map_err(|e| AppError::BadRequest(e.to_string()))
+}
+
 #[handler]
-async fn upload_task(req: &mut Request) -> AppResult {
+async fn upload_task(req: &mut Request, depot: &mut Depot) -> AppResult {
     let file = req.file("file").await.ok_or(anyhow!("No file uploaded"))?;
-    // 如果目标目录存在，删除它
-    let dest_dir = Path::new("./tasks").join(file.name().ok_or(anyhow!("File name not found"))?);
-    if dest_dir.exists() {
-        fs::remove_dir_all(&dest_dir)?;
-    }
-    // 解压到 tasks 目录
-    unpack_zip(file.path(), &dest_dir)?;
-    // 解析目标里的config.toml
-    let config_path = dest_dir.join("config.toml");
-    if !config_path.exists() {
-        return Err(anyhow!("config.toml not found").into());
-    }
-    let content = fs::read_to_string(&config_path)?;
-    let task: Task = toml::from_str(&content).map_err(|e| anyhow!("Failed to parse config.toml: {}", e))?;
+    // 文件名必须是合法的任务标识，防止路径穿越
+    let task_id = TaskId::from_dir_name(file.name().ok_or(anyhow!("File name not found"))?)?;
+    let zip_path = file.path().clone();
+    let signature = req.form::<String>("signature").await;
+    // 每次上传都是一个新版本，旧版本保持不变。版本号在存快照时才确定，并发上传时不会重复
+    let next = DB.get_versions(&task_id)?.last().map(|v| v.version).unwrap_or(0) + 1;
+    // 校验通过后才存成新版本，文件内容按哈希存进 blob，与已有内容相同的不再重复保存
+    let staging_dir = work_dir(&task_id);
+    let staging_id = task_id.clone();
+    let (task, hash, version) = blocking(move || {
+        let staged = stage_version(&zip_path, &staging_dir, signature.as_deref(), &staging_id).and_then(|task| {
+            let hash = content_hash(&staging_dir)?;
+            let (version, _) = store::save_version(&staging_id, next, &staging_dir)?;
+            Ok((task, hash, version))
+        });
+        // 无论成功与否都清掉临时目录，校验失败时最新版本不受影响
+        if staging_dir.exists() {
+            fs::remove_dir_all(&staging_dir)?;
+        }
+        staged
+    })
+    .await?;
     // 插入数据库
     DB.add_task(&task)?;
-    Ok("upload successfully".into())
+    DB.add_version(&TaskVersion {
+        task: task_id.dir_name(),
+        version,
+        timestamp: unix_now(),
+        hash,
+        actor: actor(depot),
+        rollback_of: None,
+    })?;
+    Ok(format!("upload successfully, version {}", version).into())
+}
+
+#[handler]
+async fn list_versions(req: &mut Request) -> AppResult {
+    let task_id = task_id_from_param(req)?;
+    Ok(DB.get_versions(&task_id)?.into())
+}
+
+/// 回滚：用旧版本的快照生成一个新的最新版本，历史记录保持不变
+#[handler]
+async fn rollback_task(req: &mut Request, depot: &mut Depot) -> AppResult {
+    let task_id = task_id_from_param(req)?;
+    let target = req.form::<u32>("version").await.ok_or(anyhow!("Version not found"))?;
+    let versions = DB.get_versions(&task_id)?;
+    let old = versions
+        .iter()
+        .find(|v| v.version == target)
+        .ok_or(AppError::BadRequest(format!("version {} of {} not found", target, task_id)))?;
+    let snapshot = store::load_version(&task_id, target)?
+        .ok_or(AppError::BadRequest(format!("version {} of {} not found", target, task_id)))?;
+    // 旧版本的参数和依赖也要符合当前的任务集合
+    let task = store::read_task(&snapshot).map_err(|e| AppError::BadRequest(e.to_string()))?;
+    check_task(&task)?;
+    let next = versions.last().map(|v| v.version).unwrap_or(0) + 1;
+    // 只复制快照，文件内容仍是原来的 blob
+    let copy_id = task_id.clone();
+    let version = blocking(move || Ok(store::copy_version(&copy_id, &snapshot, next)?)).await?;
+    DB.add_task(&task)?;
+    DB.add_version(&TaskVersion {
+        task: task_id.dir_name(),
+        version,
+        timestamp: unix_now(),
+        hash: old.hash.clone(),
+        actor: actor(depot),
+        rollback_of: Some(target),
+    })?;
+    Ok(format!("rolled back to version {} as version {}", target, version).into())
 }
 
 #[handler]
 async fn delete_task(req: &mut Request) -> AppResult {
-    let task_name = req.form::<String>("name").await.ok_or(anyhow!("Task name not found"))?;
-    let task_uuid = req.form::<String>("uuid").await.ok_or(anyhow!("Task UUID not found"))?;
-    let task = format!("{}-{}", task_name, task_uuid);
-    let task_dir = Path::new("./tasks").join(&task);
-    // 检查任务目录是否存在
-    if !task_dir.exists() || !task_dir.is_dir() {
-        return Err(anyhow!("Task not found").into());
-    }
-    // 删除任务目录
-    fs::remove_dir_all(&task_dir)?;
+    let task_id = task_id_from_form(req).await?;
+    remove_task(&task_id)
+}
+
+#[handler]
+async fn delete_task_by_id(req: &mut Request) -> AppResult {
+    let task_id = task_id_from_param(req)?;
+    remove_task(&task_id)
+}
+
+/// 删除任务的所有版本、数据库记录和密钥，blob 由 `/blobs/gc` 回收
+fn remove_task(task_id: &TaskId) -> AppResult {
+    // 检查任务是否存在
+    if store::versions(task_id)?.is_empty() {
+        return Err(TaskRefError::NotFound(task_id.to_string()).into());
+    }
+    // 还有任务依赖它或 profile 包含它时不允许删除
+    let tasks = DB.get_all_tasks()?;
+    if let Some(task) = tasks.iter().find(|t| t.id().ok().as_ref() == Some(task_id)) {
+        let dependents: Vec<_> = dependents(&tasks, task).iter().map(|t| t.name.clone()).collect();
+        if !dependents.is_empty() {
+            return Err(AppError::BadRequest(format!(
+                "task {} is required by {}",
+                task_id,
+                dependents.join(", ")
+            )));
+        }
+        let profiles: Vec<_> = DB
+            .get_profiles()?
+            .into_iter()
+            .filter(|p| p.uses(&tasks, task))
+            .map(|p| p.name)
+            .collect();
+        if !profiles.is_empty() {
+            return Err(AppError::BadRequest(format!(
+                "task {} is used by profile {}",
+                task_id,
+                profiles.join(", ")
+            )));
+        }
+    }
+    // 删除所有版本的快照
+    store::remove_versions(task_id)?;
     // 从数据库删除任务
-    DB.delete_tasks(&task_uuid, &task_name)?;
+    DB.delete_task(task_id)?;
+    // 任务的密钥和历史版本记录一并删除
+    DB.delete_secrets(task_id)?;
+    DB.delete_versions(task_id)?;
+    // 只被这个任务引用的 blob 留给 `/blobs/gc` 回收，删除时不扫描整个存储
     Ok("delete successfully".into())
 }
 
+#[handler]
+async fn list_profiles() -> AppResult {
+    Ok(DB.get_profiles()?.into())
+}
+
+/// 从路径参数 name 读取 profile
+fn profile_from_param(req: &Request) -> Result<Profile, AppError> {
+    let name = req.param::<String>("name").ok_or(anyhow!("Profile name not found"))?;
+    Ok(DB.get_profile(&name)?.ok_or(ProfileError::NotFound(name))?)
+}
+
+#[handler]
+async fn get_profile(req: &mut Request) -> AppResult {
+    Ok(profile_from_param(req)?.into())
+}
+
+/// 创建或替换 profile，其中的任务必须都存在，参数值必须合法
+#[handler]
+async fn set_profile(req: &mut Request) -> AppResult {
+    let profile: Profile = req
+        .parse_json()
+        .await
+        .map_err(|e| AppError::BadRequest(format!("invalid profile: {}", e)))?;
+    profile.check(&DB.get_all_tasks()?)?;
+    DB.set_profile(&profile)?;
+    Ok("profile saved".into())
+}
+
+#[handler]
+async fn delete_profile(req: &mut Request) -> AppResult {
+    let profile = profile_from_param(req)?;
+    DB.delete_profile(&profile.name)?;
+    Ok("profile deleted".into())
+}
+
+/// 从表单的 secret 字段解析密钥名
+async fn secret_name_from_form(req: &mut Request) -> Result<String, AppError> {
+    let name = req.form::<String>("secret").await.ok_or(anyhow!("Secret name not found"))?;
+    if !is_valid_secret_name(&name) {
+        return Err(AppError::BadRequest(format!(
+            "invalid secret name `{}`, use letters, digits and `_`",
+            name
+        )));
+    }
+    Ok(name)
+}
+
+#[handler]
+async fn list_secrets(req: &mut Request) -> AppResult {
+    let task_id = task_id_from_form(req).await?;
+    let secrets: Vec<SecretInfo> = DB
+        .get_secrets(&task_id)?
+        .into_iter()
+        .map(|s| SecretInfo {
+            name: s.name,
+            updated_at: s.updated_at,
+        })
+        .collect();
+    Ok(secrets.into())
+}
+
+#[handler]
+async fn set_secret(req: &mut Request) -> AppResult {
+    let task_id = task_id_from_form(req).await?;
+    let name = secret_name_from_form(req).await?;
+    let value = req.form::<String>("value").await.ok_or(anyhow!("Secret value not found"))?;
+    if value.len() > MAX_SECRET_SIZE {
+        return Err(AppError::BadRequest(format!(
+            "secret value exceeds {} bytes",
+            MAX_SECRET_SIZE
+        )));
+    }
+    // 只能给已有的任务设置密钥
+    DB.get_task(&task_id).map_err(|_| TaskRefError::NotFound(task_id.to_string()))?;
+    let secret = VAULT.encrypt(&task_id.dir_name(), &name, &value, unix_now())?;
+    DB.set_secret(&secret)?;
+    Ok("secret saved".into())
+}
+
+#[handler]
+async fn delete_secret(req: &mut Request) -> AppResult {
+    let task_id = task_id_from_form(req).await?;
+    let name = secret_name_from_form(req).await?;
+    DB.delete_secret(&task_id, &name)?;
+    Ok("secret deleted".into())
+}
+
+/// 解密后下发给运行任务的主机，只放在响应里，不进任务包
+#[handler]
+async fn fetch_secrets(req: &mut Request) -> AppResult {
+    let task_id = task_id_from_form(req).await?;
+    let mut secrets = HashMap::new();
+    for secret in DB.get_secrets(&task_id)? {
+        let value = VAULT.decrypt(&secret)?;
+        secrets.insert(secret.name, value);
+    }
+    Ok(secrets.into())
+}
+
 #[handler]
 async fn update_database() -> AppResult {
-    DB.update()?;
-    Ok(().into())
+    Ok(blocking(|| Ok(DB.update()?)).await?.into())
 }
 
+/// 把 ./tasks 下的任务目录导入为新版本
 #[handler]
-async fn auth_middleware(req: &mut Request, res: &mut Response) {
-    let auth_header: Option<String> = req.header("Authorization");
-    if let Some(auth_header) = auth_header {
-        if auth_header != CFG.server.password {
-            res.stuff(StatusCode::UNAUTHORIZED, Json(json!("Unauthorized")));
+async fn import_tasks(depot: &mut Depot) -> AppResult {
+    let actor = actor(depot);
+    Ok(blocking(move || Ok(DB.import_tasks(&actor)?)).await?.into())
+}
+
+/// 回收没有被任何版本引用的 blob
+#[handler]
+async fn collect_garbage() -> AppResult {
+    Ok(blocking(|| Ok(store::collect_garbage()?)).await?.into())
+}
+
+#[handler]
+async fn list_audit(req: &mut Request) -> AppResult {
+    let filter = AuditFilter {
+        actor: req.query("actor"),
+        task: req.query("task"),
+        action: req.query("action"),
+        since: req.query("since"),
+        until: req.query("until"),
+        limit: req.query("limit"),
+    };
+    Ok(DB.get_audit(&filter)?.into())
+}
+
+/// 从请求里取出涉及的任务：路径参数、表单的 name 和 uuid，或者上传的文件名
+async fn audited_task(req: &mut Request) -> Option<TaskId> {
+    if let Ok(id) = task_id_from_param(req) {
+        return Some(id);
+    }
+    if let Ok(id) = task_id_from_form(req).await {
+        return Some(id);
+    }
+    let file = req.file("file").await?;
+    TaskId::from_dir_name(file.name()?).ok()
+}
+
+/// 任务最新版本内容的哈希，任务不存在时为空
+fn task_hash(id: &TaskId) -> Option<String> {
+    DB.get_versions(id).ok()?.last().map(|v| v.hash.clone())
+}
+
+/// 记录修改操作的审计日志，需挂在 auth_middleware 之后、权限检查之前，被拒绝的操作也会记录。
+/// 操作类型要在 `AUDIT_ACTIONS` 里登记
+struct Audit(&'static str);
+
+#[handler]
+impl Audit {
+    async fn handle(&self, req: &mut Request, depot: &mut Depot, res: &mut Response, ctrl: &mut FlowCtrl) {
+        debug_assert!(AUDIT_ACTIONS.contains(&self.0), "unregistered audit action {}", self.0);
+        let task = audited_task(req).await;
+        let previous_hash = task.as_ref().and_then(task_hash);
+        ctrl.call_next(req, depot, res).await;
+        let status = res.status_code.unwrap_or(StatusCode::OK);
+        let record = AuditRecord {
+            timestamp: unix_now(),
+            actor: actor(depot),
+            source_ip: client_ip(req),
+            action: self.0.to_string(),
+            task: task.as_ref().map(|id| id.to_string()),
+            previous_hash,
+            new_hash: task.as_ref().and_then(task_hash),
+            status: status.as_u16(),
+            success: status.is_success(),
+        };
+        info!(
+            "Audit: {} {} {} by {} from {}: {}",
+            record.action,
+            record.task.as_deref().unwrap_or("-"),
+            record.new_hash.as_deref().unwrap_or("-"),
+            record.actor,
+            record.source_ip,
+            record.status
+        );
+        if let Err(e) = DB.add_audit(&record) {
+            error!("Failed to write audit record: {}", e);
         }
-    } else {
+    }
+}
+
+#[handler]
+async fn auth_middleware(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    // 双向 TLS 下优先使用客户端证书的身份
+    if let Some(identity) =
+        client_cert_subject(&req.remote_addr().to_string()).and_then(|s| authenticate_cert(&s))
+    {
+        debug!("Authenticated as {} by client certificate", identity.name);
+        depot.inject(identity);
+        return;
+    }
+    let auth_header: Option<String> = req.header("Authorization");
+    let Some(auth_header) = auth_header else {
         res.stuff(
             StatusCode::UNAUTHORIZED,
             Json(json!("Missing Authorization header")),
         );
+        return;
+    };
+    let signed = SignedHeader::parse(&auth_header);
+    let token_name = match &signed {
+        Some(signed) => signed.token.clone(),
+        None => auth_header.split_once(':').map(|(name, _)| name).unwrap_or_default().to_string(),
+    };
+    // 同时按来源 IP 和令牌名计数，只给存在的令牌计数，避免随意的名字撑大计数表
+    let mut keys = vec![format!("ip:{}", client_ip(req))];
+    if CFG.server.tokens.iter().any(|t| t.name == token_name) {
+        keys.push(format!("token:{}", token_name));
+    }
+    let now = Instant::now();
+    if let Some(wait) = keys.iter().filter_map(|key| FAILURES.locked(key, now)).max() {
+        too_many_requests(res, wait);
+        return;
+    }
+    let identity = match signed {
+        // 令牌、签名密钥和时间戳不对时不读取请求体，避免未认证的请求占用内存
+        Some(signed) if signing_token(&signed).is_none() => None,
+        Some(signed) => {
+            // 签名覆盖请求体，读出后再放回去供后续 handler 解析
+            let body = match req.payload_with_max_size(CFG.server.max_signed_body_size).await {
+                Ok(body) => body.clone(),
+                Err(e) => {
+                    res.stuff(StatusCode::BAD_REQUEST, Json(json!(e.to_string())));
+                    return;
+                }
+            };
+            req.replace_body(body.clone().into());
+            let path = req
+                .uri()
+                .path_and_query()
+                .map(|p| p.as_str())
+                .unwrap_or("/")
+                .to_string();
+            authenticate_signed(&signed, req.method().as_str(), &path, &body)
+        }
+        None => authenticate(&auth_header),
+    };
+    match identity {
+        Some(identity) => {
+            debug!("Authenticated as {}", identity.name);
+            keys.iter().for_each(|key| FAILURES.succeed(key));
+            depot.inject(identity);
+        }
+        None => {
+            for key in &keys {
+                if let Some(duration) = FAILURES.fail(key, now) {
+                    warn!("Too many failed logins for {}, locked for {:?}", key, duration);
+                }
+            }
+            res.stuff(StatusCode::UNAUTHORIZED, Json(json!("Unauthorized")));
+        }
+    }
+}
+
+/// 路由级别的权限检查，需挂在 auth_middleware 之后
+struct RequireScope(Scope);
+
+#[handler]
+impl RequireScope {
+    async fn handle(&self, depot: &mut Depot, res: &mut Response) {
+        let allowed = depot
+            .obtain::<Identity>()
+            .map(|identity| identity.allows(self.0))
+            .unwrap_or(false);
+        if !allowed {
+            res.stuff(StatusCode::FORBIDDEN, Json(json!("Forbidden")));
+        }
     }
 }
 
+/// 创建路由，配置了限速的路径会挂上对应的限速器
+fn route(path: &'static str) -> Router {
+    ROUTE_PATTERNS.lock().unwrap_or_else(|e| e.into_inner()).insert(path);
+    let router = Router::with_path(path);
+    match CFG.limits.rates.get(path) {
+        Some(rate) => router.hoop(RateLimit::new(*rate)),
+        None => router,
+    }
+}
+
+/// 检查 `[limits.rates]` 的每个键都是注册过的路由模式，写错的键在启动时报错而不是被悄悄忽略。
+/// 需在 create_router 之后调用
+pub fn check_rate_limits() -> Result<(), String> {
+    let patterns = ROUTE_PATTERNS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut unknown: Vec<&str> = CFG
+        .limits
+        .rates
+        .keys()
+        .map(|key| key.as_str())
+        .filter(|key| !patterns.contains(key))
+        .collect();
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    unknown.sort();
+    let known: Vec<&str> = patterns.iter().copied().collect();
+    Err(format!(
+        "unknown route {} in [limits.rates], use one of {}",
+        unknown.join(", "),
+        known.join(", ")
+    ))
+}
+
 pub fn create_router() -> Router {
     Router::new()
         .hoop(auth_middleware)
-        .push(Router::with_path("/tasks").get(list_tasks))
-        .push(Router::with_path("/tasks/download").post(download_task))
-        .push(Router::with_path("/tasks/upload").post(upload_task))
-        .push(Router::with_path("/tasks/delete").post(delete_task))
-        .push(Router::with_path("/tasks/update").get(update_database))
-}   
\ No newline at end of file
+        .push(
+            route("/tasks")
+                .hoop(RequireScope(Scope::Read))
+                .get(list_tasks),
+        )
+        .push(
+            route("/tasks/download")
+                .hoop(RequireScope(Scope::Read))
+                .post(download_task),
+        )
+        .push(
+            route("/tasks/upload")
+                .hoop(Audit("upload"))
+                .hoop(RequireScope(Scope::Upload))
+                .post(upload_task),
+        )
+        .push(
+            route("/tasks/delete")
+                .hoop(Audit("delete"))
+                .hoop(RequireScope(Scope::Delete))
+                .post(delete_task),
+        )
+        .push(
+            route("/tasks/update")
+                .hoop(Audit("update"))
+                .hoop(RequireScope(Scope::Admin))
+                .get(update_database),
+        )
+        .push(
+            route("/tasks/import")
+                .hoop(Audit("import"))
+                .hoop(RequireScope(Scope::Admin))
+                .post(import_tasks),
+        )
+        .push(
+            route("/blobs/gc")
+                .hoop(Audit("gc"))
+                .hoop(RequireScope(Scope::Admin))
+                .post(collect_garbage),
+        )
+        .push(
+            route("/tasks/{id}/versions")
+                .hoop(RequireScope(Scope::Read))
+                .get(list_versions),
+        )
+        .push(
+            route("/tasks/{id}/rollback")
+                .hoop(Audit("rollback"))
+                .hoop(RequireScope(Scope::Upload))
+                .post(rollback_task),
+        )
+        .push(
+            route("/tasks/secrets/list")
+                .hoop(RequireScope(Scope::Upload))
+                .post(list_secrets),
+        )
+        .push(
+            route("/tasks/secrets/set")
+                .hoop(Audit("secret-set"))
+                .hoop(RequireScope(Scope::Upload))
+                .post(set_secret),
+        )
+        .push(
+            route("/tasks/secrets/delete")
+                .hoop(Audit("secret-rm"))
+                .hoop(RequireScope(Scope::Upload))
+                .post(delete_secret),
+        )
+        // 运行任务的主机只有 read 令牌，因此 read 就能取到解密后的密钥，而列出密钥名要 upload
+        .push(
+            route("/tasks/secrets/fetch")
+                .hoop(RequireScope(Scope::Read))
+                .post(fetch_secrets),
+        )
+        // 放在固定路径之后，避免 download、upload 等被当成任务名
+        .push(
+            route("/tasks/{id}")
+                .hoop(RequireScope(Scope::Read))
+                .get(get_task),
+        )
+        .push(
+            route("/tasks/{id}")
+                .hoop(Audit("delete"))
+                .hoop(RequireScope(Scope::Delete))
+                .delete(delete_task_by_id),
+        )
+        .push(
+            route("/profiles")
+                .hoop(RequireScope(Scope::Read))
+                .get(list_profiles),
+        )
+        .push(
+            route("/profiles")
+                .hoop(Audit("profile-set"))
+                .hoop(RequireScope(Scope::Upload))
+                .post(set_profile),
+        )
+        .push(
+            route("/profiles/{name}")
+                .hoop(RequireScope(Scope::Read))
+                .get(get_profile),
+        )
+        .push(
+            route("/profiles/{name}")
+                .hoop(Audit("profile-rm"))
+                .hoop(RequireScope(Scope::Delete))
+                .delete(delete_profile),
+        )
+        .push(
+            route("/audit")
+                .hoop(RequireScope(Scope::Admin))
+                .get(list_audit),
+        )
+}
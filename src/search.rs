@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Task;
+
+/// 搜索任务的条件，对应 `GET /tasks` 的查询参数，都不填时返回全部任务且保持原有顺序
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TaskQuery {
+    /// 包含该标签的任务
+    pub tag: Option<String>,
+    pub category: Option<String>,
+    /// 在任务名和描述里匹配的文本，不区分大小写
+    pub q: Option<String>,
+    /// 排序字段：name 或 category，前面加 `-` 表示倒序
+    pub sort: Option<String>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+impl TaskQuery {
+    /// 检查排序字段是否合法
+    pub fn check(&self) -> Result<(), String> {
+        match self.sort.as_deref().map(|s| s.trim_start_matches('-')) {
+            None | Some("name") | Some("category") => Ok(()),
+            Some(other) => Err(format!("unknown sort field `{}`, use name or category", other)),
+        }
+    }
+
+    /// 判断任务是否满足过滤条件
+    pub fn matches(&self, task: &Task) -> bool {
+        let tag = self
+            .tag
+            .as_ref()
+            .is_none_or(|tag| task.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)));
+        let category = self
+            .category
+            .as_ref()
+            .is_none_or(|c| task.category.as_ref().is_some_and(|tc| tc.eq_ignore_ascii_case(c)));
+        let text = self.q.as_ref().is_none_or(|q| {
+            let q = q.to_lowercase();
+            task.name.to_lowercase().contains(&q) || task.description.to_lowercase().contains(&q)
+        });
+        tag && category && text
+    }
+
+    /// 过滤、排序并分页
+    pub fn apply(&self, tasks: Vec<Task>) -> Vec<Task> {
+        let mut tasks: Vec<Task> = tasks.into_iter().filter(|t| self.matches(t)).collect();
+        if let Some(sort) = &self.sort {
+            let (field, descending) = match sort.strip_prefix('-') {
+                Some(field) => (field, true),
+                None => (sort.as_str(), false),
+            };
+            match field {
+                "category" => tasks.sort_by(|a, b| {
+                    (&a.category, &a.name).cmp(&(&b.category, &b.name))
+                }),
+                _ => tasks.sort_by(|a, b| a.name.cmp(&b.name)),
+            }
+            if descending {
+                tasks.reverse();
+            }
+        }
+        tasks
+            .into_iter()
+            .skip(self.offset.unwrap_or(0))
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(name: &str, category: Option<&str>, tags: &[&str]) -> Task {
+        Task {
+            uuid: String::new(),
+            name: name.to_string(),
+            description: format!("Install {}", name),
+            params: Vec::new(),
+            depends_on: Vec::new(),
+            tags: tags.iter().map(|s| s.to_string()).collect(),
+            category: category.map(|s| s.to_string()),
+            platform: None,
+            actions: Default::default(),
+            modes: Default::default(),
+        }
+    }
+
+    fn names(tasks: &[Task]) -> Vec<&str> {
+        tasks.iter().map(|t| t.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_task_query() {
+        let tasks = vec![
+            task("nginx", Some("web"), &["proxy"]),
+            task("docker", Some("base"), &["container"]),
+            task("caddy", Some("web"), &["proxy", "tls"]),
+        ];
+        let all = TaskQuery::default().apply(tasks.clone());
+        assert_eq!(names(&all), ["nginx", "docker", "caddy"]);
+        let query = TaskQuery {
+            tag: Some("PROXY".to_string()),
+            sort: Some("name".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(names(&query.apply(tasks.clone())), ["caddy", "nginx"]);
+        let query = TaskQuery {
+            q: Some("install dock".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(names(&query.apply(tasks.clone())), ["docker"]);
+        let query = TaskQuery {
+            sort: Some("-category".to_string()),
+            limit: Some(2),
+            offset: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(names(&query.apply(tasks)), ["caddy", "docker"]);
+        assert!(TaskQuery { sort: Some("size".to_string()), ..Default::default() }.check().is_err());
+    }
+}
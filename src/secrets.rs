@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+/// 单个密钥值允许的最大字节数
+pub const MAX_SECRET_SIZE: usize = 64 * 1024;
+
+/// 列出任务的密钥时返回的信息，不含密钥的值
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecretInfo {
+    pub name: String,
+    /// 最后修改的 Unix 时间戳（秒）
+    pub updated_at: u64,
+}
+
+/// 密钥以环境变量的形式传给 run.sh，名字必须是合法的环境变量名
+pub fn is_valid_secret_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && name.len() <= 128
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_name() {
+        assert!(is_valid_secret_name("API_KEY"));
+        assert!(is_valid_secret_name("_token2"));
+        assert!(!is_valid_secret_name(""));
+        assert!(!is_valid_secret_name("2FA"));
+        assert!(!is_valid_secret_name("DB-PASSWORD"));
+        assert!(!is_valid_secret_name("A=B"));
+    }
+}
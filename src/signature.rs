@@ -0,0 +1,129 @@
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// 签名模式下 Authorization 头使用的认证方案名
+pub const SIGNATURE_SCHEME: &str = "HMAC-SHA256";
+
+/// 解析后的签名认证头：`HMAC-SHA256 token=..,ts=..,nonce=..,sig=..`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedHeader {
+    pub token: String,
+    pub timestamp: u64,
+    pub nonce: String,
+    pub signature: String,
+}
+
+impl SignedHeader {
+    /// 从 Authorization 头解析，不是签名模式或格式错误时返回 None
+    pub fn parse(value: &str) -> Option<Self> {
+        let params = value.strip_prefix(SIGNATURE_SCHEME)?.trim_start();
+        let (mut token, mut timestamp, mut nonce, mut signature) = (None, None, None, None);
+        for pair in params.split(',') {
+            let (key, val) = pair.trim().split_once('=')?;
+            match key {
+                "token" => token = Some(val.to_string()),
+                "ts" => timestamp = Some(val.parse().ok()?),
+                "nonce" => nonce = Some(val.to_string()),
+                "sig" => signature = Some(val.to_string()),
+                _ => return None,
+            }
+        }
+        Some(SignedHeader {
+            token: token?,
+            timestamp: timestamp?,
+            nonce: nonce?,
+            signature: signature?,
+        })
+    }
+
+    /// 生成一个当前时间、随机 nonce 的签名认证头
+    pub fn sign(token: &str, key: &str, method: &str, path: &str, body: &[u8]) -> Self {
+        let timestamp = unix_now();
+        let nonce = uuid::Uuid::new_v4().simple().to_string();
+        let signature = sign_request(key, method, path, body, timestamp, &nonce);
+        SignedHeader {
+            token: token.to_string(),
+            timestamp,
+            nonce,
+            signature,
+        }
+    }
+
+    /// 常数时间校验签名
+    pub fn verify(&self, key: &str, method: &str, path: &str, body: &[u8]) -> bool {
+        let Ok(signature) = hex::decode(&self.signature) else {
+            return false;
+        };
+        let mut mac = new_mac(key);
+        mac.update(string_to_sign(method, path, body, self.timestamp, &self.nonce).as_bytes());
+        mac.verify_slice(&signature).is_ok()
+    }
+}
+
+impl std::fmt::Display for SignedHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} token={},ts={},nonce={},sig={}",
+            SIGNATURE_SCHEME, self.token, self.timestamp, self.nonce, self.signature
+        )
+    }
+}
+
+/// 待签名的字符串：方法、路径、请求体 SHA-256、时间戳和 nonce，以换行分隔
+fn string_to_sign(method: &str, path: &str, body: &[u8], timestamp: u64, nonce: &str) -> String {
+    format!(
+        "{}\n{}\n{:x}\n{}\n{}",
+        method.to_ascii_uppercase(),
+        path,
+        Sha256::digest(body),
+        timestamp,
+        nonce
+    )
+}
+
+fn new_mac(key: &str) -> HmacSha256 {
+    HmacSha256::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length")
+}
+
+/// 计算请求签名，返回十六进制字符串
+pub fn sign_request(
+    key: &str,
+    method: &str,
+    path: &str,
+    body: &[u8],
+    timestamp: u64,
+    nonce: &str,
+) -> String {
+    let mut mac = new_mac(key);
+    mac.update(string_to_sign(method, path, body, timestamp, nonce).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// 当前的 Unix 时间戳（秒）
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let header = SignedHeader::sign("ci", "key", "post", "/tasks/upload", b"body");
+        let parsed = SignedHeader::parse(&header.to_string()).unwrap();
+        assert_eq!(parsed, header);
+        assert!(parsed.verify("key", "POST", "/tasks/upload", b"body"));
+        assert!(!parsed.verify("other", "POST", "/tasks/upload", b"body"));
+        assert!(!parsed.verify("key", "POST", "/tasks/delete", b"body"));
+        assert!(!parsed.verify("key", "POST", "/tasks/upload", b"tampered"));
+        assert!(SignedHeader::parse("ci:password").is_none());
+    }
+}
@@ -0,0 +1,187 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, RwLock};
+
+use deploycli::{BlobStore, GcReport, MANIFEST_FILE, Snapshot, Task, TaskId, parse_manifest};
+use log::info;
+use uuid::Uuid;
+
+/// 历史版本的存放目录，每个版本一个快照 `<name>-<uuid>/<版本号>.json`
+const VERSIONS_DIR: &str = "./versions";
+
+/// 文件内容按 SHA-256 存放的目录，所有任务和版本共用
+const BLOBS_DIR: &str = "./blobs";
+
+pub static STORE: LazyLock<BlobStore> = LazyLock::new(|| BlobStore::new(BLOBS_DIR));
+
+/// 写入快照时持读锁，回收时持写锁，避免刚写入、还没有快照引用的 blob 被回收。
+/// 这是阻塞锁，路由里要经 `spawn_blocking` 调用拿锁的函数，不能在异步任务里直接等待
+static GC_LOCK: RwLock<()> = RwLock::new(());
+
+fn task_dir(task_id: &TaskId) -> PathBuf {
+    Path::new(VERSIONS_DIR).join(task_id.dir_name())
+}
+
+fn version_path(task_id: &TaskId, version: u32) -> PathBuf {
+    task_dir(task_id).join(format!("{}.json", version))
+}
+
+/// 任务已有的版本号，从小到大
+pub fn versions(task_id: &TaskId) -> std::io::Result<Vec<u32>> {
+    let dir = task_dir(task_id);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut versions = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        if let Some(version) = name.strip_suffix(".json").and_then(|v| v.parse().ok()) {
+            versions.push(version);
+        }
+    }
+    versions.sort();
+    Ok(versions)
+}
+
+/// 读取某个版本的快照，不存在时返回 None
+pub fn load_version(task_id: &TaskId, version: u32) -> std::io::Result<Option<Snapshot>> {
+    let path = version_path(task_id, version);
+    if !path.exists() {
+        return Ok(None);
+    }
+    Snapshot::load(&path).map(Some)
+}
+
+/// 最新版本的快照
+pub fn load_latest(task_id: &TaskId) -> std::io::Result<Option<Snapshot>> {
+    match versions(task_id)?.last() {
+        Some(&version) => load_version(task_id, version),
+        None => Ok(None),
+    }
+}
+
+/// 把快照存成一个新版本，版本号取 first 和已有最大版本号加一中较大的那个，被占用时依次往后找，
+/// 返回实际使用的版本号。先写临时文件再用硬链接占住版本号，目标已存在时链接失败，
+/// 并发的上传不会拿到同一个版本号，读取方也不会看到写了一半的快照
+fn claim_version(task_id: &TaskId, snapshot: &Snapshot, first: u32) -> std::io::Result<u32> {
+    let dir = task_dir(task_id);
+    fs::create_dir_all(&dir)?;
+    let tmp = dir.join(format!(".{}.tmp", Uuid::new_v4()));
+    fs::write(&tmp, serde_json::to_vec_pretty(snapshot)?)?;
+    let mut version = first.max(versions(task_id)?.last().map_or(1, |v| v + 1));
+    let claimed = loop {
+        match fs::hard_link(&tmp, version_path(task_id, version)) {
+            Ok(()) => break Ok(version),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => version += 1,
+            Err(e) => break Err(e),
+        }
+    };
+    fs::remove_file(&tmp)?;
+    claimed
+}
+
+/// 把目录里的内容存成任务的一个新版本，相同的文件内容只存一份，返回（版本号，快照）。
+/// first 是调用方期望的版本号，已被占用时顺延
+pub fn save_version(task_id: &TaskId, first: u32, dir: &Path) -> std::io::Result<(u32, Snapshot)> {
+    let _guard = GC_LOCK.read().unwrap_or_else(|e| e.into_inner());
+    let snapshot = STORE.snapshot(dir)?;
+    let version = claim_version(task_id, &snapshot, first)?;
+    Ok((version, snapshot))
+}
+
+/// 用已有的快照生成一个新版本，只写清单，不复制文件内容，返回实际使用的版本号
+pub fn copy_version(task_id: &TaskId, snapshot: &Snapshot, first: u32) -> std::io::Result<u32> {
+    let _guard = GC_LOCK.read().unwrap_or_else(|e| e.into_inner());
+    claim_version(task_id, snapshot, first)
+}
+
+/// 删除任务的所有版本，blob 留给下一次回收
+pub fn remove_versions(task_id: &TaskId) -> std::io::Result<()> {
+    let dir = task_dir(task_id);
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+/// 从快照里的 config.toml 解析任务
+pub fn read_task(snapshot: &Snapshot) -> anyhow::Result<Task> {
+    let content = String::from_utf8(STORE.read(snapshot, MANIFEST_FILE)?)?;
+    Ok(parse_manifest(&content)?)
+}
+
+/// 所有任务的最新版本
+pub fn latest_versions() -> std::io::Result<Vec<(TaskId, Snapshot)>> {
+    let mut latest = Vec::new();
+    if !Path::new(VERSIONS_DIR).exists() {
+        return Ok(latest);
+    }
+    for entry in fs::read_dir(VERSIONS_DIR)? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        let Ok(task_id) = TaskId::from_dir_name(&name) else {
+            continue;
+        };
+        if let Some(snapshot) = load_latest(&task_id)? {
+            latest.push((task_id, snapshot));
+        }
+    }
+    Ok(latest)
+}
+
+/// 回收没有任何版本引用的 blob。任何一个快照读取失败都会中止，不会误删仍在使用的内容
+pub fn collect_garbage() -> std::io::Result<GcReport> {
+    let _guard = GC_LOCK.write().unwrap_or_else(|e| e.into_inner());
+    let mut snapshots = Vec::new();
+    if Path::new(VERSIONS_DIR).exists() {
+        for task in fs::read_dir(VERSIONS_DIR)? {
+            let task = task?;
+            if !task.file_type()?.is_dir() {
+                continue;
+            }
+            for version in fs::read_dir(task.path())? {
+                let path = version?.path();
+                if path.extension().is_some_and(|e| e == "json") {
+                    snapshots.push(Snapshot::load(&path)?);
+                }
+            }
+        }
+    }
+    let report = STORE.gc(&snapshots)?;
+    info!(
+        "Garbage collection removed {} blob(s), {} bytes, kept {} blob(s) for {} reference(s)",
+        report.removed, report.freed_bytes, report.kept, report.references
+    );
+    Ok(report)
+}
+
+/// 把旧布局里以目录保存的版本 `<name>-<uuid>/<版本号>/` 转成快照，返回转换的版本数
+pub fn migrate_version_dirs() -> std::io::Result<usize> {
+    let mut migrated = 0;
+    if !Path::new(VERSIONS_DIR).exists() {
+        return Ok(migrated);
+    }
+    for task in fs::read_dir(VERSIONS_DIR)? {
+        let task = task?;
+        let Ok(task_id) = TaskId::from_dir_name(&task.file_name().to_string_lossy()) else {
+            continue;
+        };
+        for version in fs::read_dir(task.path())? {
+            let version = version?;
+            let name = version.file_name().to_string_lossy().to_string();
+            let Ok(number) = name.parse::<u32>() else {
+                continue;
+            };
+            if version.file_type()?.is_dir() {
+                let _guard = GC_LOCK.read().unwrap_or_else(|e| e.into_inner());
+                STORE.snapshot(&version.path())?.save(&version_path(&task_id, number))?;
+                fs::remove_dir_all(version.path())?;
+                migrated += 1;
+            }
+        }
+    }
+    if migrated > 0 {
+        info!("Migrated {} version directories to blob storage", migrated);
+    }
+    Ok(migrated)
+}
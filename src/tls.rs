@@ -0,0 +1,150 @@
+use anyhow::anyhow;
+use rustls::client::WebPkiServerVerifier;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::Arc;
+
+/// 证书的 SHA-256 指纹，小写十六进制，不带分隔符
+pub fn cert_fingerprint(cert: &CertificateDer<'_>) -> String {
+    hex::encode(Sha256::digest(cert.as_ref()))
+}
+
+/// 统一指纹格式，允许 `AB:CD:..` 这种带冒号的大写写法
+fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint.replace(':', "").to_ascii_lowercase()
+}
+
+/// 校验服务端证书指纹的 verifier，配置了 CA 时还会先校验证书链
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    fingerprint: String,
+    inner: Option<Arc<WebPkiServerVerifier>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if let Some(inner) = &self.inner {
+            inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+        }
+        if cert_fingerprint(end_entity) != self.fingerprint {
+            return Err(rustls::Error::General(
+                "Server certificate fingerprint mismatch".to_string(),
+            ));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// 读取 PEM 格式的 CA 证书包
+fn load_roots(ca_cert: &Path) -> anyhow::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(ca_cert)
+        .map_err(|e| anyhow!("Failed to read CA bundle {}: {}", ca_cert.display(), e))?
+    {
+        roots.add(cert.map_err(|e| anyhow!("Invalid certificate in CA bundle: {}", e))?)?;
+    }
+    Ok(roots)
+}
+
+/// 读取 PEM 格式的客户端证书链和私钥
+fn load_client_auth(
+    cert: &Path,
+    key: &Path,
+) -> anyhow::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| anyhow!("Failed to read client certificate {}: {}", cert.display(), e))?;
+    let key = PrivateKeyDer::from_pem_file(key)
+        .map_err(|e| anyhow!("Failed to read client key {}: {}", key.display(), e))?;
+    Ok((certs, key))
+}
+
+/// 构建客户端 TLS 配置：只信任给定的 CA 证书包（默认使用内置根证书），或者只认指定指纹的证书，
+/// 也可以两者同时校验；给出客户端证书和私钥时用于双向 TLS
+pub fn client_tls_config(
+    ca_cert: Option<&Path>,
+    fingerprint: Option<&str>,
+    client_auth: Option<(&Path, &Path)>,
+) -> anyhow::Result<ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let roots = ca_cert.map(load_roots).transpose()?;
+    let builder = match fingerprint {
+        Some(fingerprint) => {
+            let inner = roots
+                .map(|roots| {
+                    WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                        .build()
+                })
+                .transpose()?;
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier {
+                    fingerprint: normalize_fingerprint(fingerprint),
+                    inner,
+                    provider,
+                }))
+        }
+        None => builder.with_root_certificates(roots.unwrap_or_else(|| RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        })),
+    };
+    let config = match client_auth {
+        Some((cert, key)) => {
+            let (certs, key) = load_client_auth(cert, key)?;
+            builder.with_client_auth_cert(certs, key)?
+        }
+        None => builder.with_no_client_auth(),
+    };
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_fingerprint() {
+        assert_eq!(normalize_fingerprint("AB:cd:01"), "abcd01");
+        let cert = CertificateDer::from(vec![1u8, 2, 3]);
+        assert_eq!(cert_fingerprint(&cert).len(), 64);
+    }
+}
@@ -1,69 +1,523 @@
 use colored::Colorize;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::{fs, io};
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Component, Path};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use uuid::Uuid;
 use zip::ZipWriter;
 use zip::write::SimpleFileOptions;
 
+use crate::audit::{civil_from_days, days_from_civil};
+use crate::package::{PackageEntry, package_entries};
+use crate::params::Param;
+use crate::platform::Platform;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Task {
     pub uuid: String,
     pub name: String,
     pub description: String,
+    /// 运行前需要填写的参数
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub params: Vec<Param>,
+    /// 运行前需要先运行的任务，填任务名或 uuid
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+    /// 用于搜索的标签
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    pub category: Option<String>,
+    /// 任务能运行的平台
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<Platform>,
+    /// 动作名到脚本的映射，如 install、uninstall、upgrade、verify
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub actions: BTreeMap<String, String>,
+    /// 解压后强制设置的权限，路径到八进制权限的映射，如 `"files/id_rsa" = "0600"`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub modes: BTreeMap<String, String>,
+}
+
+impl Task {
+    /// 校验并返回任务标识
+    pub fn id(&self) -> Result<TaskId, TaskIdError> {
+        TaskId::parse(&self.name, &self.uuid)
+    }
+}
+
+/// 任务的一个历史版本，每次上传或回滚生成一个新版本，内容不再改变
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TaskVersion {
+    /// 任务标识 `<name>-<uuid>`
+    pub task: String,
+    /// 从 1 开始递增的版本号
+    pub version: u32,
+    /// Unix 时间戳（秒）
+    pub timestamp: u64,
+    /// 任务内容的哈希
+    pub hash: String,
+    /// 上传者的令牌名
+    pub actor: String,
+    /// 由回滚生成时，记录回滚到的版本
+    pub rollback_of: Option<u32>,
+}
+
+/// 任务名允许的最大长度
+const MAX_TASK_NAME_LEN: usize = 64;
+
+#[derive(Error, Debug)]
+pub enum TaskIdError {
+    #[error("invalid task name `{0}`, only ASCII letters, digits, `-` and `_` are allowed")]
+    InvalidName(String),
+    #[error("invalid task uuid `{0}`")]
+    InvalidUuid(String),
+    #[error("invalid task identifier `{0}`, expected `<name>-<uuid>`")]
+    InvalidDirName(String),
+}
+
+/// 经过校验的任务标识，任务目录名为 `<name>-<uuid>`，不会包含路径分隔符或 `..`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TaskId {
+    name: String,
+    uuid: Uuid,
+}
+
+impl TaskId {
+    pub fn parse(name: &str, uuid: &str) -> Result<Self, TaskIdError> {
+        let valid_name = !name.is_empty()
+            && name.len() <= MAX_TASK_NAME_LEN
+            && !name.starts_with('-')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name {
+            return Err(TaskIdError::InvalidName(name.to_string()));
+        }
+        let uuid = Uuid::try_parse(uuid).map_err(|_| TaskIdError::InvalidUuid(uuid.to_string()))?;
+        Ok(TaskId {
+            name: name.to_string(),
+            uuid,
+        })
+    }
+
+    /// 从 `<name>-<uuid>` 形式的目录名解析
+    pub fn from_dir_name(dir_name: &str) -> Result<Self, TaskIdError> {
+        // 带连字符的 uuid 固定 36 个字符
+        let split = dir_name
+            .len()
+            .checked_sub(37)
+            .filter(|&i| dir_name.is_char_boundary(i) && dir_name[i..].starts_with('-'))
+            .ok_or_else(|| TaskIdError::InvalidDirName(dir_name.to_string()))?;
+        Self::parse(&dir_name[..split], &dir_name[split + 1..])
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn uuid(&self) -> String {
+        self.uuid.to_string()
+    }
+
+    /// 任务目录名
+    pub fn dir_name(&self) -> String {
+        format!("{}-{}", self.name, self.uuid)
+    }
+}
+
+impl std::fmt::Display for TaskId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.dir_name())
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum TaskRefError {
+    #[error("no task matches `{0}`")]
+    NotFound(String),
+    #[error("`{0}` matches more than one task: {1}, use the uuid instead")]
+    Ambiguous(String, String),
+}
+
+/// 按 `<name>-<uuid>`、完整 uuid、任务名或唯一的 uuid 前缀查找任务，依次匹配
+pub fn find_task<'a>(tasks: &'a [Task], reference: &str) -> Result<&'a Task, TaskRefError> {
+    let exact = tasks.iter().find(|t| {
+        t.uuid.eq_ignore_ascii_case(reference) || t.id().is_ok_and(|id| id.dir_name() == reference)
+    });
+    if let Some(task) = exact {
+        return Ok(task);
+    }
+    let unique = |matches: Vec<&'a Task>| match matches.as_slice() {
+        [] => None,
+        [task] => Some(Ok(*task)),
+        _ => {
+            let ids: Vec<String> = matches.iter().map(|t| format!("{}-{}", t.name, t.uuid)).collect();
+            Some(Err(TaskRefError::Ambiguous(reference.to_string(), ids.join(", "))))
+        }
+    };
+    let by_name = tasks.iter().filter(|t| t.name == reference).collect();
+    if let Some(result) = unique(by_name) {
+        return result;
+    }
+    let prefix = reference.to_ascii_lowercase();
+    let by_prefix = tasks
+        .iter()
+        .filter(|t| !prefix.is_empty() && t.uuid.to_ascii_lowercase().starts_with(&prefix))
+        .collect();
+    unique(by_prefix).unwrap_or_else(|| Err(TaskRefError::NotFound(reference.to_string())))
+}
+
+/// Unix 时间戳转成 zip 的时间（UTC，精度 2 秒），超出 zip 能表示的 1980 到 2107 年时返回 None
+fn zip_time(timestamp: u64) -> Option<zip::DateTime> {
+    let secs = timestamp % 86400;
+    let (year, month, day) = civil_from_days((timestamp / 86400) as i64);
+    zip::DateTime::from_date_and_time(
+        u16::try_from(year).ok()?,
+        month as u8,
+        day as u8,
+        (secs / 3600) as u8,
+        (secs % 3600 / 60) as u8,
+        (secs % 60) as u8,
+    )
+    .ok()
+}
+
+/// zip 的时间转回 SystemTime，zip 的默认时间（1980-01-01 00:00:00）视为没有记录
+fn system_time(time: zip::DateTime) -> Option<SystemTime> {
+    if time == zip::DateTime::default() {
+        return None;
+    }
+    let days = days_from_civil(time.year().into(), time.month().into(), time.day().into());
+    let secs = days * 86400 + i64::from(time.hour()) * 3600 + i64::from(time.minute()) * 60 + i64::from(time.second());
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+/// 文件的 unix 权限，不保留 setuid、setgid 和 sticky 位
+pub(crate) fn file_mode(metadata: &fs::Metadata) -> u32 {
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.permissions().mode() & 0o777
+    }
+    // 其他平台没有 unix 权限，沿用原来的 0755，保证脚本可以执行
+    #[cfg(not(target_family = "unix"))]
+    {
+        let _ = metadata;
+        0o755
+    }
+}
+
+/// 文件的修改时间（Unix 时间戳），取不到时为 None
+pub(crate) fn file_mtime(metadata: &fs::Metadata) -> Option<u64> {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+}
+
+/// 按文件的实际权限和修改时间生成条目选项
+fn entry_options(metadata: &fs::Metadata) -> SimpleFileOptions {
+    let mut options = SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Zstd)
+        .unix_permissions(file_mode(metadata));
+    if let Some(time) = file_mtime(metadata).and_then(zip_time) {
+        options = options.last_modified_time(time);
+    }
+    options
+}
+
+/// 设置 unix 权限，其他平台忽略
+pub(crate) fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))
+    }
+    #[cfg(not(target_family = "unix"))]
+    {
+        let _ = (path, mode);
+        Ok(())
+    }
+}
+
+/// 解析八进制的权限，如 `0600` 或 `755`，不接受 0777 以外的位
+pub fn parse_mode(mode: &str) -> Option<u32> {
+    let digits = mode.strip_prefix("0o").unwrap_or(mode);
+    if digits.is_empty() || digits.len() > 4 {
+        return None;
+    }
+    u32::from_str_radix(digits, 8).ok().filter(|&m| m <= 0o777)
+}
+
+/// 路径是否是只包含普通组成部分的相对路径
+pub fn is_plain_relative(path: &str) -> bool {
+    !path.is_empty() && Path::new(path).components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// 按清单里的 `[modes]` 设置解压后文件的权限，符号链接不跟随
+pub fn apply_modes(dir: &Path, modes: &BTreeMap<String, String>) -> io::Result<()> {
+    for (path, mode) in modes {
+        let parsed = parse_mode(mode)
+            .filter(|_| is_plain_relative(path))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid mode {} for {}", mode, path)))?;
+        let relative = Path::new(path);
+        if through_symlink(dir, relative) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cannot set the mode of {} through a symlink", path),
+            ));
+        }
+        set_mode(&dir.join(relative), parsed)?;
+    }
+    Ok(())
 }
 
 pub fn create_zip(src_dir: &Path, zip_path: &Path) -> std::io::Result<()> {
     let file = fs::File::create(zip_path)?;
     let mut zip = ZipWriter::new(file);
 
-    let options = SimpleFileOptions::default()
-        .compression_method(zip::CompressionMethod::Zstd)
-        .unix_permissions(0o755)
-        .last_modified_time(zip::DateTime::default());
+    // 递归打包，保留相对路径、空目录和符号链接，以及每个条目的权限和修改时间
+    for (name, entry) in package_entries(src_dir)? {
+        let options = entry_options(&fs::symlink_metadata(src_dir.join(&name))?);
+        match entry {
+            PackageEntry::Dir => zip.add_directory(name, options)?,
+            PackageEntry::Symlink(target) => zip.add_symlink(name, target.to_string_lossy(), options)?,
+            PackageEntry::File(path) => {
+                zip.start_file(name, options)?;
+                let mut f = fs::File::open(&path)?;
+                let mut buffer = Vec::new();
+                f.read_to_end(&mut buffer)?;
+                zip.write_all(&buffer)?;
+            }
+        }
+    }
+    zip.finish()?;
+    Ok(())
+}
 
-    for entry in fs::read_dir(src_dir)? {
+/// 递归复制目录，目标目录不存在时创建，符号链接复制为链接
+pub fn copy_dir(src: &Path, dest: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(src)? {
         let entry = entry?;
-        let path = entry.path();
-        if path.is_file() {
-            let name = path.file_name().unwrap().to_string_lossy().to_string();
-            zip.start_file(name, options)?;
-            let mut f = fs::File::open(&path)?;
-            let mut buffer = Vec::new();
-            f.read_to_end(&mut buffer)?;
-            zip.write_all(&buffer)?;
+        let target = dest.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            create_symlink(&fs::read_link(entry.path())?, &target)?;
+        } else if file_type.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
         }
     }
-    zip.finish()?;
     Ok(())
 }
 
-pub fn unpack_zip(src_path: &Path, dest_dir: &Path) -> std::io::Result<()> {
+#[cfg(target_family = "unix")]
+pub(crate) fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(target_family = "unix"))]
+pub(crate) fn create_symlink(_target: &Path, link: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("cannot create symlink {} on this platform", link.display()),
+    ))
+}
+
+/// 链接目标只能是相对路径：开头若干个 `..`，之后只有普通的路径，且 `..` 不超出包的根目录
+fn is_safe_link(relative: &Path, target: &Path) -> bool {
+    let depth = relative.parent().map_or(0, |p| p.components().count());
+    let mut ups = 0;
+    let mut descended = false;
+    for component in target.components() {
+        match component {
+            Component::ParentDir if !descended => ups += 1,
+            Component::Normal(_) => descended = true,
+            Component::CurDir => {}
+            _ => return false,
+        }
+    }
+    !target.as_os_str().is_empty() && ups <= depth
+}
+
+/// relative 在 dest_dir 里的各级父目录或它本身是否是符号链接，是的话写入会被重定向到别处
+fn through_symlink(dest_dir: &Path, relative: &Path) -> bool {
+    let mut path = dest_dir.to_path_buf();
+    relative.components().any(|component| {
+        path.push(component);
+        path.symlink_metadata().is_ok_and(|m| m.file_type().is_symlink())
+    })
+}
+
+/// 解压时的资源限制，防止 zip 炸弹
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UnpackLimits {
+    /// 最多的条目数
+    pub max_entries: usize,
+    /// 解压后的总字节数上限
+    pub max_total_size: u64,
+    /// 单个文件解压后的字节数上限
+    pub max_file_size: u64,
+    /// 单个条目允许的最大压缩比
+    pub max_ratio: u64,
+}
+
+impl Default for UnpackLimits {
+    fn default() -> Self {
+        UnpackLimits {
+            max_entries: 10_000,
+            max_total_size: 1024 * 1024 * 1024,
+            max_file_size: 512 * 1024 * 1024,
+            max_ratio: 1000,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum UnpackError {
+    #[error("archive has more than {0} entries")]
+    TooManyEntries(usize),
+    #[error("archive exceeds the total size limit of {0} bytes")]
+    TooLarge(u64),
+    #[error("entry `{0}` exceeds the file size limit of {1} bytes")]
+    FileTooLarge(String, u64),
+    #[error("entry `{0}` exceeds the compression ratio limit of {1}")]
+    SuspiciousRatio(String, u64),
+    #[error("entry `{0}` has an unsafe path")]
+    UnsafePath(String),
+    #[error("entry `{0}` is a symlink pointing outside the package")]
+    Symlink(String),
+    #[error("invalid zip archive: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl UnpackError {
+    /// 是否是超出资源限制导致的错误
+    pub fn is_limit_exceeded(&self) -> bool {
+        matches!(
+            self,
+            UnpackError::TooManyEntries(_)
+                | UnpackError::TooLarge(_)
+                | UnpackError::FileTooLarge(..)
+                | UnpackError::SuspiciousRatio(..)
+        )
+    }
+}
+
+/// 使用默认限制解压
+pub fn unpack_zip(src_path: &Path, dest_dir: &Path) -> Result<(), UnpackError> {
+    unpack_zip_with_limits(src_path, dest_dir, &UnpackLimits::default())
+}
+
+pub fn unpack_zip_with_limits(
+    src_path: &Path,
+    dest_dir: &Path,
+    limits: &UnpackLimits,
+) -> Result<(), UnpackError> {
     // 如果dest_dir不存在，则创建它
     if !dest_dir.exists() {
         fs::create_dir_all(dest_dir)?;
     }
     let file = fs::File::open(src_path)?;
     let mut archive = zip::ZipArchive::new(file)?;
+    if archive.len() > limits.max_entries {
+        return Err(UnpackError::TooManyEntries(limits.max_entries));
+    }
 
+    let mut total_size: u64 = 0;
+    // 目录的权限和时间在所有内容写完后再设置，避免只读目录无法写入、写入子项又改掉时间
+    let mut dirs = Vec::new();
     for i in 0..archive.len() {
         let mut file = archive.by_index(i)?;
-        let out_path = dest_dir.join(file.mangled_name());
-        if file.name().ends_with('/') {
-            fs::create_dir_all(&out_path)?;
-        } else {
+        let name = file.name().to_string();
+        // 只接受解压后仍在 dest_dir 内的相对路径
+        let relative = file
+            .enclosed_name()
+            .ok_or_else(|| UnpackError::UnsafePath(name.clone()))?;
+        let out_path = dest_dir.join(&relative);
+        if through_symlink(dest_dir, &relative) {
+            return Err(UnpackError::UnsafePath(name));
+        }
+        if file.is_symlink() {
+            let mut target = String::new();
+            (&mut file).take(4096).read_to_string(&mut target)?;
+            if !is_safe_link(&relative, Path::new(&target)) {
+                return Err(UnpackError::Symlink(name));
+            }
             if let Some(parent) = out_path.parent() {
                 fs::create_dir_all(parent)?;
             }
-            let mut out_file = fs::File::create(&out_path)?;
-            std::io::copy(&mut file, &mut out_file)?;
+            create_symlink(Path::new(&target), &out_path)?;
+            continue;
+        }
+        let mode = file.unix_mode();
+        let modified = file.last_modified().and_then(system_time);
+        if file.is_dir() {
+            fs::create_dir_all(&out_path)?;
+            dirs.push((out_path, mode, modified));
+            continue;
+        }
+        // 先按头部声明的大小检查，实际写入时再按真实字节数检查一次
+        if file.size() > limits.max_file_size {
+            return Err(UnpackError::FileTooLarge(name, limits.max_file_size));
+        }
+        if file.compressed_size() > 0 && file.size() / file.compressed_size() > limits.max_ratio {
+            return Err(UnpackError::SuspiciousRatio(name, limits.max_ratio));
+        }
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut out_file = fs::File::create(&out_path)?;
+        let allowed = limits
+            .max_file_size
+            .min(limits.max_total_size - total_size);
+        let written = std::io::copy(&mut (&mut file).take(allowed.saturating_add(1)), &mut out_file)?;
+        if written > limits.max_file_size {
+            return Err(UnpackError::FileTooLarge(name, limits.max_file_size));
+        }
+        total_size += written;
+        if total_size > limits.max_total_size {
+            return Err(UnpackError::TooLarge(limits.max_total_size));
+        }
+        if let Some(modified) = modified {
+            out_file.set_modified(modified)?;
+        }
+        if let Some(mode) = mode {
+            set_mode(&out_path, mode)?;
+        }
+    }
+    for (path, mode, modified) in dirs.into_iter().rev() {
+        if let Some(mode) = mode {
+            set_mode(&path, mode)?;
+        }
+        // 有的平台不能打开目录来设置时间，只影响时间，忽略
+        if let Some(modified) = modified
+            && let Ok(dir) = fs::File::open(&path)
+        {
+            let _ = dir.set_modified(modified);
         }
     }
     Ok(())
 }
 
-pub fn run_script(script_path: &Path) {
+/// 显示并在确认后运行某个动作的脚本，envs 只注入到脚本进程的环境变量里，返回脚本是否运行成功
+pub fn run_script(script_path: &Path, action: &str, envs: &HashMap<String, String>) -> bool {
     // 在运行之前先完整显示脚本内容，等待用户输入y同意执行
+    println!(
+        "{} {} ({})",
+        "Action:".green().bold(),
+        action.bold(),
+        script_path.display()
+    );
     println!("{}", "Script content:".green().bold());
     let mut script_content = String::new();
     let mut file = fs::File::open(script_path).expect("Failed to open script file");
@@ -79,12 +533,13 @@ pub fn run_script(script_path: &Path) {
     let trimmed = input.trim();
     if !trimmed.eq_ignore_ascii_case("y") {
         println!("{}", "Script execution cancelled.".red().bold());
-        return;
+        return false;
     }
     #[cfg(target_family = "unix")]
     {
         let mut child = std::process::Command::new("sh")
             .arg(script_path)
+            .envs(envs)
             .stdin(std::process::Stdio::piped())
             .stdout(std::process::Stdio::inherit()) // 直接继承父进程的 stdout
             .stderr(std::process::Stdio::inherit()) // 直接继承父进程的 stderr
@@ -119,10 +574,38 @@ pub fn run_script(script_path: &Path) {
         // 等待脚本执行完成
         let status = child.wait().expect("Failed to wait on child");
         if status.success() {
-            println!("{}", "Script executed successfully.".green().bold());
+            println!("{} {}", "Action completed successfully:".green().bold(), action);
         } else {
-            eprintln!("{} {:?}", "Script execution failed with status".red().bold(), status);
+            eprintln!(
+                "{} {} {:?}",
+                "Action failed:".red().bold(),
+                action,
+                status
+            );
         }
+        status.success()
+    }
+    #[cfg(not(target_family = "unix"))]
+    false
+}
+
+/// 运行检查脚本，脚本的标准输出被丢弃，返回退出码是否为 0。这里不再询问，调用方负责先让用户确认
+pub fn run_check(script_path: &Path, envs: &HashMap<String, String>) -> io::Result<bool> {
+    #[cfg(target_family = "unix")]
+    {
+        let status = std::process::Command::new("sh")
+            .arg(script_path)
+            .envs(envs)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::inherit())
+            .status()?;
+        Ok(status.success())
+    }
+    #[cfg(not(target_family = "unix"))]
+    {
+        let _ = (script_path, envs);
+        Err(io::Error::new(io::ErrorKind::Unsupported, "check scripts need a unix shell"))
     }
 }
 
@@ -148,4 +631,229 @@ mod tests {
         fs::remove_file(zip_path).unwrap();
         fs::remove_dir_all(dest_dir).unwrap();
     }
+
+    /// 测试用的任务目录：子目录、符号链接、不同的权限和修改时间
+    #[cfg(target_family = "unix")]
+    fn sample_tree(root: &Path) -> PathBuf {
+        let src_dir = root.join("src");
+        fs::create_dir_all(src_dir.join("files/etc/nginx")).unwrap();
+        fs::create_dir_all(src_dir.join("logs")).unwrap();
+        fs::write(src_dir.join("run.sh"), "echo hi").unwrap();
+        fs::write(src_dir.join("files/etc/nginx/site.conf"), "listen 80;").unwrap();
+        create_symlink(Path::new("files/etc/nginx/site.conf"), &src_dir.join("site.conf")).unwrap();
+        create_symlink(Path::new("../run.sh"), &src_dir.join("logs/run.sh")).unwrap();
+        set_mode(&src_dir.join("files/etc/nginx/site.conf"), 0o600).unwrap();
+        set_mode(&src_dir.join("run.sh"), 0o750).unwrap();
+        set_mode(&src_dir.join("logs"), 0o700).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(src_dir.join("run.sh"))
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+            .unwrap();
+        src_dir
+    }
+
+    /// 打包 sample_tree 再解压，返回（源目录，解压目录）
+    #[cfg(target_family = "unix")]
+    fn round_trip(root: &Path) -> (PathBuf, PathBuf) {
+        let src_dir = sample_tree(root);
+        let zip_path = root.join("task.zip");
+        create_zip(&src_dir, &zip_path).unwrap();
+        let dest_dir = root.join("out");
+        unpack_zip(&zip_path, &dest_dir).unwrap();
+        (src_dir, dest_dir)
+    }
+
+    /// 相对于 root 的条目列表，用于比较两个目录
+    #[cfg(target_family = "unix")]
+    fn relative_entries(root: &Path) -> Vec<(String, PackageEntry)> {
+        package_entries(root)
+            .unwrap()
+            .into_iter()
+            .map(|(name, entry)| match entry {
+                PackageEntry::File(path) => (name, PackageEntry::File(path.strip_prefix(root).unwrap().to_path_buf())),
+                entry => (name, entry),
+            })
+            .collect()
+    }
+
+    #[cfg(target_family = "unix")]
+    fn mode(path: &Path) -> u32 {
+        use std::os::unix::fs::PermissionsExt;
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_zip_round_trip_entries() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (src_dir, dest_dir) = round_trip(tmp.path());
+        let expected = relative_entries(&src_dir);
+        assert_eq!(relative_entries(&dest_dir), expected);
+        assert!(expected.contains(&("logs".to_string(), PackageEntry::Dir)));
+        assert_eq!(fs::read_to_string(dest_dir.join("site.conf")).unwrap(), "listen 80;");
+        assert!(fs::symlink_metadata(dest_dir.join("site.conf")).unwrap().file_type().is_symlink());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_zip_round_trip_modes_and_mtime() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (_, dest_dir) = round_trip(tmp.path());
+        assert_eq!(mode(&dest_dir.join("site.conf")), 0o600);
+        assert_eq!(mode(&dest_dir.join("run.sh")), 0o750);
+        assert_eq!(mode(&dest_dir.join("logs")), 0o700);
+        assert_eq!(
+            fs::metadata(dest_dir.join("run.sh")).unwrap().modified().unwrap(),
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_zip_round_trip_content_hash() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (src_dir, dest_dir) = round_trip(tmp.path());
+        assert_eq!(crate::content_hash(&src_dir).unwrap(), crate::content_hash(&dest_dir).unwrap());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_apply_modes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = sample_tree(tmp.path());
+        let modes = BTreeMap::from([("run.sh".to_string(), "0700".to_string())]);
+        apply_modes(&dir, &modes).unwrap();
+        assert_eq!(mode(&dir.join("run.sh")), 0o700);
+        // 不能经由符号链接修改包外文件的权限
+        let through_link = BTreeMap::from([("site.conf".to_string(), "0644".to_string())]);
+        assert!(apply_modes(&dir, &through_link).is_err());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_copy_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let src_dir = sample_tree(tmp.path());
+        let copy_dir_path = tmp.path().join("copy");
+        copy_dir(&src_dir, &copy_dir_path).unwrap();
+        assert_eq!(relative_entries(&copy_dir_path), relative_entries(&src_dir));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_unpack_unsafe_symlinks() {
+        let unpack = |entries: &[(&str, Option<&str>)]| {
+            let tmp = tempfile::tempdir().unwrap();
+            let zip_path = tmp.path().join("links.zip");
+            let mut zip = ZipWriter::new(fs::File::create(&zip_path).unwrap());
+            for (name, target) in entries {
+                match target {
+                    Some(target) => zip.add_symlink(*name, *target, SimpleFileOptions::default()).unwrap(),
+                    None => {
+                        zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+                        zip.write_all(b"x").unwrap();
+                    }
+                }
+            }
+            zip.finish().unwrap();
+            unpack_zip(&zip_path, &tmp.path().join("out"))
+        };
+        assert!(matches!(unpack(&[("etc", Some("/etc"))]), Err(UnpackError::Symlink(_))));
+        assert!(matches!(unpack(&[("a/up", Some("../.."))]), Err(UnpackError::Symlink(_))));
+        assert!(matches!(unpack(&[("up", Some("a/../.."))]), Err(UnpackError::Symlink(_))));
+        assert!(matches!(unpack(&[("a", Some(".")), ("a/x", None)]), Err(UnpackError::UnsafePath(_))));
+        assert!(unpack(&[("a/b/up", Some("../c")), ("here", Some("."))]).is_ok());
+    }
+
+    #[test]
+    fn test_unpack_limits() {
+        let tmp = tempfile::tempdir().unwrap();
+        let src_dir = tmp.path().join("src");
+        fs::create_dir_all(&src_dir).unwrap();
+        fs::write(src_dir.join("big"), vec![0u8; 1024 * 1024]).unwrap();
+        let zip_path = tmp.path().join("big.zip");
+        create_zip(&src_dir, &zip_path).unwrap();
+        let dest_dir = tmp.path().join("out");
+        let limits = UnpackLimits {
+            max_file_size: 1024,
+            ..Default::default()
+        };
+        let err = unpack_zip_with_limits(&zip_path, &dest_dir, &limits).unwrap_err();
+        assert!(matches!(err, UnpackError::FileTooLarge(..)));
+        let limits = UnpackLimits {
+            max_ratio: 10,
+            ..Default::default()
+        };
+        let err = unpack_zip_with_limits(&zip_path, &dest_dir, &limits).unwrap_err();
+        assert!(matches!(err, UnpackError::SuspiciousRatio(..)));
+        let limits = UnpackLimits {
+            max_total_size: 1024,
+            max_ratio: u64::MAX,
+            ..Default::default()
+        };
+        let err = unpack_zip_with_limits(&zip_path, &dest_dir, &limits).unwrap_err();
+        assert!(matches!(err, UnpackError::TooLarge(_)));
+        let limits = UnpackLimits {
+            max_ratio: u64::MAX,
+            ..Default::default()
+        };
+        unpack_zip_with_limits(&zip_path, &dest_dir, &limits).unwrap();
+        assert_eq!(fs::metadata(dest_dir.join("big")).unwrap().len(), 1024 * 1024);
+    }
+
+    #[test]
+    fn test_task_id() {
+        let uuid = "6f1c4a52-9b1e-4f6d-8c1a-2b3c4d5e6f70";
+        let id = TaskId::parse("nginx_site-1", uuid).unwrap();
+        assert_eq!(id.dir_name(), format!("nginx_site-1-{}", uuid));
+        assert_eq!(TaskId::from_dir_name(&id.dir_name()).unwrap(), id);
+        assert!(TaskId::parse("../../etc", uuid).is_err());
+        assert!(TaskId::parse("a/b", uuid).is_err());
+        assert!(TaskId::parse("", uuid).is_err());
+        assert!(TaskId::parse("name", "../x").is_err());
+        assert!(TaskId::from_dir_name("../../etc").is_err());
+        assert!(TaskId::from_dir_name(&format!("../x-{}", uuid)).is_err());
+    }
+
+    #[test]
+    fn test_find_task() {
+        let task = |name: &str, uuid: &str| Task {
+            uuid: uuid.to_string(),
+            name: name.to_string(),
+            description: String::new(),
+            params: Vec::new(),
+            depends_on: Vec::new(),
+            tags: Vec::new(),
+            category: None,
+            platform: None,
+            actions: Default::default(),
+            modes: Default::default(),
+        };
+        let tasks = vec![
+            task("nginx", "6f1c4a52-9b1e-4f6d-8c1a-2b3c4d5e6f70"),
+            task("docker", "6f2d0000-9b1e-4f6d-8c1a-2b3c4d5e6f70"),
+            task("docker", "a0000000-9b1e-4f6d-8c1a-2b3c4d5e6f70"),
+        ];
+        assert_eq!(find_task(&tasks, "nginx").unwrap().uuid, tasks[0].uuid);
+        assert_eq!(find_task(&tasks, "6F2D").unwrap().uuid, tasks[1].uuid);
+        assert_eq!(find_task(&tasks, &tasks[2].uuid).unwrap().uuid, tasks[2].uuid);
+        assert_eq!(find_task(&tasks, &format!("docker-{}", tasks[2].uuid)).unwrap().uuid, tasks[2].uuid);
+        assert!(matches!(find_task(&tasks, "docker"), Err(TaskRefError::Ambiguous(..))));
+        assert!(matches!(find_task(&tasks, "6f"), Err(TaskRefError::Ambiguous(..))));
+        assert_eq!(find_task(&tasks, "b").unwrap_err(), TaskRefError::NotFound("b".to_string()));
+        assert!(find_task(&tasks, "").is_err());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_run_check() {
+        let tmp = tempfile::tempdir().unwrap();
+        let script = tmp.path().join("check.sh");
+        fs::write(&script, "echo checking\n[ \"$DEPLOY_PARAM_STATE\" = present ]\n").unwrap();
+        let envs = |state: &str| HashMap::from([("DEPLOY_PARAM_STATE".to_string(), state.to_string())]);
+        assert!(run_check(&script, &envs("present")).unwrap());
+        assert!(!run_check(&script, &envs("absent")).unwrap());
+    }
 }
@@ -0,0 +1,128 @@
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+
+use anyhow::anyhow;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::config::CFG;
+
+/// 数据库里保存的加密密钥，密文绑定了任务和密钥名，不能挪给别的任务用
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredSecret {
+    /// 任务标识 `<name>-<uuid>`
+    pub task: String,
+    pub name: String,
+    pub nonce: String,
+    pub ciphertext: String,
+    pub updated_at: u64,
+}
+
+/// 用主密钥加解密任务密钥
+pub struct Vault {
+    cipher: XChaCha20Poly1305,
+}
+
+impl Vault {
+    /// 读取主密钥文件，不存在时生成一个只有属主可读的新密钥
+    pub fn load(key_file: &Path) -> anyhow::Result<Self> {
+        if !key_file.exists() {
+            let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+            write_private(key_file, hex::encode(key).as_bytes())?;
+            info!("Generated new secrets key at {}", key_file.display());
+        }
+        let key = hex::decode(fs::read_to_string(key_file)?.trim())
+            .map_err(|e| anyhow!("Invalid secrets key {}: {}", key_file.display(), e))?;
+        if key.len() != 32 {
+            return Err(anyhow!("Secrets key {} must be 32 bytes", key_file.display()));
+        }
+        Ok(Vault {
+            cipher: XChaCha20Poly1305::new(Key::from_slice(&key)),
+        })
+    }
+
+    pub fn encrypt(&self, task: &str, name: &str, value: &str, updated_at: u64) -> anyhow::Result<StoredSecret> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let aad = associated_data(task, name);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, Payload { msg: value.as_bytes(), aad: &aad })
+            .map_err(|_| anyhow!("Failed to encrypt secret {}", name))?;
+        Ok(StoredSecret {
+            task: task.to_string(),
+            name: name.to_string(),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+            updated_at,
+        })
+    }
+
+    pub fn decrypt(&self, secret: &StoredSecret) -> anyhow::Result<String> {
+        let nonce = hex::decode(&secret.nonce)?;
+        if nonce.len() != 24 {
+            return Err(anyhow!("Invalid nonce for secret {}", secret.name));
+        }
+        let ciphertext = hex::decode(&secret.ciphertext)?;
+        let aad = associated_data(&secret.task, &secret.name);
+        let plaintext = self
+            .cipher
+            .decrypt(XNonce::from_slice(&nonce), Payload { msg: &ciphertext, aad: &aad })
+            .map_err(|_| anyhow!("Failed to decrypt secret {}", secret.name))?;
+        Ok(String::from_utf8(plaintext)?)
+    }
+}
+
+fn associated_data(task: &str, name: &str) -> Vec<u8> {
+    format!("{}\0{}", task, name).into_bytes()
+}
+
+/// 写入只有属主可读写的文件
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    #[cfg(target_family = "unix")]
+    {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(path)?
+            .write_all(contents)
+    }
+    #[cfg(not(target_family = "unix"))]
+    {
+        fs::write(path, contents)
+    }
+}
+
+/// 全局的 Vault 实例，启动时加载
+pub static VAULT: LazyLock<Vault> = LazyLock::new(|| {
+    Vault::load(Path::new(&CFG.secrets.key_file)).expect("Failed to load secrets key")
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_and_decrypt() {
+        let tmp = tempfile::tempdir().unwrap();
+        let key_file = tmp.path().join("vault.key");
+        let vault = Vault::load(&key_file).unwrap();
+        let secret = vault.encrypt("web-1", "API_KEY", "hunter2", 0).unwrap();
+        assert!(!secret.ciphertext.contains(&hex::encode("hunter2")));
+        assert_eq!(vault.decrypt(&secret).unwrap(), "hunter2");
+        // 换了任务或名字的密文无法解密
+        let moved = StoredSecret {
+            task: "web-2".to_string(),
+            ..secret.clone()
+        };
+        assert!(vault.decrypt(&moved).is_err());
+        // 重新加载同一个主密钥仍能解密
+        let vault = Vault::load(&key_file).unwrap();
+        assert_eq!(vault.decrypt(&secret).unwrap(), "hunter2");
+    }
+}